@@ -1,3 +1 @@
-cargo run
-gcc ./out/output.c -lgc -o ./out/output
-./out/output
\ No newline at end of file
+cargo run -- run test/1.fun
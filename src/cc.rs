@@ -0,0 +1,142 @@
+use std::env;
+use std::fs;
+use std::path::{Path,PathBuf};
+use std::process;
+
+use crate::diagnostic::{self,Diagnostic};
+use crate::runtime::Memory;
+
+// compilers tried, in order, when $CC is not set
+const CANDIDATES : [&str; 3] = ["cc", "gcc", "clang"];
+
+#[derive(Debug, Default)]
+pub struct CcOptions {
+    pub opt_level : Option<String>, // "0".."3" or "s"
+    pub libs : Vec<String>,
+    pub lib_dirs : Vec<String>,
+    pub memory : Memory, // decides whether -lgc is needed
+}
+
+#[derive(Debug)]
+pub struct Compiler {
+    program : PathBuf,
+    args : Vec<String>, // from $CC, e.g. CC="ccache gcc"
+}
+
+fn find_in_path(name : &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+pub fn find_compiler() -> Result<Compiler, Diagnostic> {
+    if let Ok(cc) = env::var("CC") {
+        let mut words = cc.split_whitespace().map(|w| w.to_string());
+        if let Some(program) = words.next() {
+            return Ok(Compiler { program : PathBuf::from(program), args : words.collect() });
+        }
+    }
+
+    for name in CANDIDATES {
+        if let Some(program) = find_in_path(name) {
+            return Ok(Compiler { program, args : vec![] });
+        }
+    }
+
+    Err(Diagnostic::error(diagnostic::NO_C_COMPILER, "No C compiler found".to_string())
+        .with_note(format!("looked for $CC, then {} in PATH", CANDIDATES.join(", "))))
+}
+
+// the first line of compiler output that names an error, without the file prefix
+fn first_error(stderr : &str) -> Option<String> {
+    stderr.lines()
+        .find(|line| line.contains("error"))
+        .map(|line| match line.find("error") {
+            Some(i) => line[i..].to_string(),
+            None => line.to_string(),
+        })
+}
+
+fn missing_library(stderr : &str) -> Option<String> {
+    for marker in ["cannot find -l", "library not found for -l"] {
+        if let Some(i) = stderr.find(marker) {
+            let rest = &stderr[i + marker.len()..];
+            let name : String = rest.chars().take_while(|c| !c.is_whitespace() && *c != ':' && *c != '\'').collect();
+            return Some(name);
+        }
+    }
+    None
+}
+
+// "fatal error: gc.h: No such file or directory" (gcc) / "fatal error: 'gc.h' file not found" (clang)
+fn missing_header(stderr : &str) -> Option<String> {
+    for line in stderr.lines().filter(|l| l.contains("fatal error")) {
+        let rest = &line[line.find("fatal error").unwrap() + "fatal error:".len()..];
+        if let Some(name) = rest.strip_suffix(": No such file or directory") {
+            return Some(name.trim().to_string());
+        }
+        if let Some(name) = rest.strip_suffix(" file not found") {
+            return Some(name.trim().trim_matches('\'').to_string());
+        }
+    }
+    None
+}
+
+impl Compiler {
+    pub fn name(&self) -> String {
+        self.program.display().to_string()
+    }
+
+    pub fn compile(&self, c_path : &Path, exe : &Path, options : &CcOptions) -> Result<(), Diagnostic> {
+        let mut cmd = process::Command::new(&self.program);
+        cmd.args(&self.args);
+        if let Some(level) = &options.opt_level {
+            cmd.arg(format!("-O{}", level));
+        }
+        // FunLang's int arithmetic wraps, like the interpreter's; without this,
+        // signed overflow is undefined and the optimizer assumes it can't happen
+        cmd.arg("-fwrapv");
+        cmd.arg(c_path).arg("-o").arg(exe);
+        for dir in &options.lib_dirs {
+            cmd.arg(format!("-L{}", dir));
+        }
+        for lib in options.memory.libs() {
+            cmd.arg(format!("-l{}", lib));
+        }
+        for lib in &options.libs {
+            cmd.arg(format!("-l{}", lib));
+        }
+
+        let output = cmd.output().map_err(|e| {
+            Diagnostic::error(diagnostic::NO_C_COMPILER, format!("Could not run C compiler '{}': {}", self.name(), e))
+        })?;
+        if output.status.success() {
+            return Ok(());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if let Some(header) = missing_header(&stderr) {
+            return Err(Diagnostic::error(diagnostic::MISSING_LIBRARY, format!("The C compiler could not find header '{}'", header))
+                .with_note("the default build needs the Boehm GC development files (gc.h and libgc)".to_string())
+                .with_note("--memory=arena or --memory=malloc builds without them".to_string()));
+        }
+        if let Some(lib) = missing_library(&stderr) {
+            return Err(Diagnostic::error(diagnostic::MISSING_LIBRARY, format!("The linker could not find library '{}'", lib))
+                .with_note("install it or point to it with -L <dir>".to_string()));
+        }
+
+        // anything else means we generated C the compiler rejects, which is our bug;
+        // keep the file around so it can be attached to a report
+        let kept = c_path.with_extension("failed.c");
+        let mut d = Diagnostic::error(diagnostic::CODEGEN_ERROR, "internal codegen error: the generated C failed to compile".to_string())
+            .with_note(format!("{} exited with {}", self.name(), output.status));
+        if let Some(line) = first_error(&stderr) {
+            d = d.with_note(format!("first C error: {}", line));
+        }
+        if fs::copy(c_path, &kept).is_ok() {
+            d = d.with_note(format!("the generated C was kept at {}", kept.display()));
+        }
+        Err(d.with_note("this is a bug in funlang, please report it".to_string()))
+    }
+}
@@ -0,0 +1,1153 @@
+use std::collections::{HashMap,HashSet};
+use std::mem;
+
+use crate::codegen;
+use crate::diagnostic::{self,Diagnostic};
+use crate::format;
+use crate::nodes::*;
+use crate::source::Span;
+
+#[derive(Debug, Clone)]
+struct VarInfo {
+    ty : Type,
+    c_name : String, // the name in C, see declare
+    span : Span, // where it was declared
+}
+
+#[derive(Debug, Clone)]
+struct FunInfo {
+    params : Vec<Type>,
+    return_type : Type,
+}
+
+#[derive(Clone)]
+pub struct Checker {
+    scopes : Vec<HashMap<String,VarInfo>>, // innermost block last
+    ended : HashMap<String,Span>, // variables whose block has closed, for better errors
+    c_names : HashSet<String>, // the C names the current function has declared
+    functions : HashMap<String,FunInfo>,
+    structs : HashMap<String,StructDef>,
+    enums : HashMap<String,EnumDef>,
+    match_count : usize, // numbers match expressions for codegen
+    current_return : Type,
+    diagnostics : Vec<Diagnostic>,
+}
+
+pub fn check_program(program : &mut Program) -> Result<(), Vec<Diagnostic>> {
+    let mut cx = Checker::new();
+    cx.program(program);
+    cx.finish()
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Self {
+            scopes : vec![],
+            ended : HashMap::new(),
+            c_names : HashSet::new(),
+            functions : HashMap::new(),
+            structs : HashMap::new(),
+            enums : HashMap::new(),
+            match_count : 0,
+            current_return : Type::Void,
+            diagnostics : vec![],
+        }
+    }
+
+    // the REPL keeps one Checker for the whole session and checks each input
+    // against it. Declarations are checked as in a file; statements run in an
+    // outer scope that is never popped, so their variables stay visible to later
+    // inputs
+    pub fn check_declarations(&mut self, program : &mut Program) -> Result<(), Vec<Diagnostic>> {
+        self.declare_types(&program.structs, &program.enums);
+        self.declare_functions(&program.functions);
+        let scopes = mem::take(&mut self.scopes);
+        let ended = mem::take(&mut self.ended);
+        let c_names = mem::take(&mut self.c_names);
+        for function in &mut program.functions {
+            self.function(function);
+        }
+        self.scopes = scopes;
+        self.ended = ended;
+        self.c_names = c_names;
+        self.finish()
+    }
+
+    pub fn check_stmts(&mut self, stmts : &mut [Stmt]) -> Result<(), Vec<Diagnostic>> {
+        if self.scopes.is_empty() {
+            self.push_scope();
+        }
+        self.current_return = Type::Void;
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<(), Vec<Diagnostic>> {
+        if self.diagnostics.is_empty() {
+            return Ok(());
+        }
+        Err(mem::take(&mut self.diagnostics))
+    }
+
+    fn report(&mut self, d : Diagnostic) {
+        self.diagnostics.push(d);
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        if let Some(scope) = self.scopes.pop() {
+            for (name, info) in scope {
+                self.ended.insert(name, info.span);
+            }
+        }
+    }
+
+    fn lookup(&self, name : &str) -> Option<&VarInfo> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    // declares `name` in the innermost scope and returns the name to use in C.
+    // C can't redeclare a name in the same block, and in `let x = x + 1;` the new
+    // x would already be in scope in its own initializer, so a variable whose
+    // name the function has used before gets a fresh name like x__1. A user
+    // can name a variable x__1 too, so the count goes on until the name is
+    // free. Either way it gets the prefix of user names in C
+    fn declare(&mut self, name : &Ident, ty : Type) -> String {
+        let mut c_name = name.name.clone();
+        let mut count = 0;
+        while self.c_names.contains(&codegen::user_name(&c_name)) || self.functions.contains_key(&c_name) {
+            count += 1;
+            c_name = format!("{}__{}", name.name, count);
+        }
+        let c_name = codegen::user_name(&c_name);
+        self.c_names.insert(c_name.clone());
+        self.ended.remove(&name.name);
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.name.clone(), VarInfo { ty, c_name : c_name.clone(), span : name.span });
+        }
+        c_name
+    }
+
+    fn undefined_variable(&mut self, name : &str, span : Span) {
+        let d = match self.ended.get(name) {
+            Some(decl) => Diagnostic::error(diagnostic::UNDEFINED_VARIABLE, format!("Variable '{}' is not in scope", name))
+                .with_span(span)
+                .with_note(format!("'{}' was declared at line {}, in a block that has already ended", name, decl.line)),
+            None => Diagnostic::error(diagnostic::UNDEFINED_VARIABLE, format!("Undefined variable: {}", name))
+                .with_span(span),
+        };
+        self.report(d);
+    }
+
+    fn program(&mut self, program : &mut Program) {
+        // every signature is collected first so calls can appear before the declaration
+        self.declare_types(&program.structs, &program.enums);
+        self.declare_functions(&program.functions);
+        for function in &mut program.functions {
+            self.function(function);
+        }
+        match &mut program.main {
+            Some(main) => self.function(main),
+            // the C compiler would only notice when linking
+            None => self.report(Diagnostic::error(diagnostic::MISSING_MAIN, "The program has no main function".to_string())
+                .with_note("a program starts at `fun main() { ... }`".to_string())),
+        }
+    }
+
+    // structs and enums go in together, since either can use the other
+    fn declare_types(&mut self, structs : &[StructDef], enums : &[EnumDef]) {
+        let mut declared = vec![];
+        for def in structs {
+            let struct_name = &def.name.name;
+            if self.type_declared(&def.name) {
+                continue;
+            }
+            let mut unique = def.clone();
+            unique.fields.clear();
+            for field in &def.fields {
+                if unique.field(&field.name.name).is_some() {
+                    self.report(Diagnostic::error(diagnostic::DUPLICATE_DEFINITION, format!(
+                        "Duplicate field '{}' in struct '{}'",
+                        field.name.name, struct_name
+                    )).with_span(field.name.span));
+                    continue;
+                }
+                unique.fields.push(field.clone());
+            }
+            self.structs.insert(struct_name.clone(), unique);
+            declared.push((&def.name, def.fields.iter().map(|field| (&field.ty, field.name.span)).collect::<Vec<_>>()));
+        }
+        for def in enums {
+            let enum_name = &def.name.name;
+            if self.type_declared(&def.name) {
+                continue;
+            }
+            let mut unique = def.clone();
+            unique.variants.clear();
+            for variant in &def.variants {
+                if unique.variant(&variant.name.name).is_some() {
+                    self.report(Diagnostic::error(diagnostic::DUPLICATE_DEFINITION, format!(
+                        "Duplicate variant '{}' in enum '{}'",
+                        variant.name.name, enum_name
+                    )).with_span(variant.name.span));
+                    continue;
+                }
+                unique.variants.push(variant.clone());
+            }
+            self.enums.insert(enum_name.clone(), unique);
+            declared.push((&def.name, def.variants.iter().flat_map(|variant| variant.payload.iter().map(|ty| (ty, variant.name.span))).collect()));
+        }
+
+        // members can name types declared further down
+        for (_, members) in &declared {
+            for (ty, span) in members {
+                self.known_type(ty, *span);
+            }
+        }
+        // C needs a type's size, so it can't contain itself other than through an array
+        for (name, members) in declared {
+            if members.iter().any(|(ty, _)| self.contains_type(ty, &name.name, &mut HashSet::new())) {
+                self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!("Type '{}' contains itself", name.name))
+                    .with_span(name.span)
+                    .with_note(format!("a type can only hold itself through an array, like `[{}]`", name.name)));
+            }
+        }
+    }
+
+    fn type_declared(&mut self, name : &Ident) -> bool {
+        if !self.structs.contains_key(&name.name) && !self.enums.contains_key(&name.name) {
+            return false;
+        }
+        self.report(Diagnostic::error(diagnostic::DUPLICATE_DEFINITION, format!("Type '{}' is declared more than once", name.name))
+            .with_span(name.span));
+        true
+    }
+
+    fn contains_type(&self, ty : &Type, target : &str, seen : &mut HashSet<String>) -> bool {
+        let Type::Named(name) = ty else {
+            return false;
+        };
+        if name == target {
+            return true;
+        }
+        if !seen.insert(name.clone()) {
+            return false;
+        }
+        if let Some(def) = self.structs.get(name) {
+            return def.fields.iter().any(|field| self.contains_type(&field.ty, target, seen));
+        }
+        match self.enums.get(name) {
+            Some(def) => def.variants.iter().flat_map(|variant| &variant.payload).any(|ty| self.contains_type(ty, target, seen)),
+            None => false,
+        }
+    }
+
+    fn unknown_type<'a>(&self, ty : &'a Type) -> Option<&'a str> {
+        match ty {
+            Type::Array(element) => self.unknown_type(element),
+            Type::Named(name) if !self.structs.contains_key(name) && !self.enums.contains_key(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    // reports type names that don't exist; `span` is whatever the type belongs to
+    fn known_type(&mut self, ty : &Type, span : Span) -> bool {
+        match self.unknown_type(ty) {
+            Some(name) => {
+                self.report(Diagnostic::error(diagnostic::UNDEFINED_TYPE, format!("Unknown type '{}'", name))
+                    .with_span(span));
+                false
+            }
+            None => true,
+        }
+    }
+
+    fn declare_functions(&mut self, functions : &[Function]) {
+        for function in functions {
+            let fun_name = &function.name.name;
+            if Builtin::from_name(fun_name).is_some() {
+                self.report(Diagnostic::error(diagnostic::DUPLICATE_DEFINITION, format!("'{}' is a builtin function and can't be redefined", fun_name))
+                    .with_span(function.name.span));
+                continue;
+            }
+            if self.functions.contains_key(fun_name) {
+                self.report(Diagnostic::error(diagnostic::DUPLICATE_DEFINITION, format!("Function '{}' is declared more than once", fun_name))
+                    .with_span(function.name.span));
+                continue;
+            }
+            if self.structs.contains_key(fun_name) || self.enums.contains_key(fun_name) {
+                self.report(Diagnostic::error(diagnostic::DUPLICATE_DEFINITION, format!("'{}' is already declared as a type", fun_name))
+                    .with_span(function.name.span));
+                continue;
+            }
+            for param in &function.params {
+                self.known_type(&param.ty, param.name.span);
+            }
+            self.known_type(&function.return_type, function.name.span);
+            for (i, param) in function.params.iter().enumerate() {
+                if function.params[..i].iter().any(|p| p.name.name == param.name.name) {
+                    self.report(Diagnostic::error(diagnostic::DUPLICATE_DEFINITION, format!(
+                        "Duplicate parameter '{}' in function '{}'",
+                        param.name.name, fun_name
+                    )).with_span(param.name.span));
+                }
+            }
+            let params = function.params.iter().map(|p| p.ty.clone()).collect();
+            self.functions.insert(fun_name.clone(), FunInfo { params, return_type : function.return_type.clone() });
+        }
+    }
+
+    fn function(&mut self, function : &mut Function) {
+        // every function body starts with nothing in scope
+        self.scopes.clear();
+        self.ended.clear();
+        self.c_names.clear();
+
+        self.push_scope();
+        for param in &mut function.params {
+            param.c_name = self.declare(&param.name, param.ty.clone());
+        }
+        self.current_return = function.return_type.clone();
+        self.block(&mut function.body);
+        self.pop_scope();
+
+        if function.return_type != Type::Void && !always_returns(&function.body) {
+            self.report(Diagnostic::error(diagnostic::MISSING_RETURN, format!(
+                "Function '{}' returns '{}', but can reach the end of its body without returning",
+                function.name.name, function.return_type
+            )).with_span(function.name.span)
+                .with_note("end the body with a return, or give every if and match branch one".to_string()));
+        }
+    }
+
+    fn block(&mut self, stmts : &mut [Stmt]) {
+        self.push_scope();
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+        self.pop_scope();
+    }
+
+    // like expr, but the expression has to produce a value
+    fn value(&mut self, e : &mut Expr) -> Option<Type> {
+        let ty = self.expr(e)?;
+        if ty == Type::Void {
+            self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, "This expression has no value".to_string())
+                .with_span(e.span)
+                .with_note("functions without a return type can only be called as statements".to_string()));
+            return None;
+        }
+        Some(ty)
+    }
+
+    // like value, but an array literal takes its element type from `expected`,
+    // which is the only way an empty one gets a type
+    fn value_as(&mut self, e : &mut Expr, expected : &Type) -> Option<Type> {
+        if let (ExprKind::Array(elements), Some(element)) = (&mut e.kind, expected.element()) {
+            let ty = self.array_literal(elements, e.span, Some(element))?;
+            e.ty = ty.clone();
+            return Some(ty);
+        }
+        self.value(e)
+    }
+
+    fn array_literal(&mut self, elements : &mut [Expr], span : Span, expected : Option<&Type>) -> Option<Type> {
+        let (element, rest) = match (expected, elements.split_first_mut()) {
+            (Some(element), _) => (element.clone(), elements),
+            (None, Some((first, rest))) => (self.value(first)?, rest),
+            (None, None) => {
+                self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, "Can't tell the element type of an empty array".to_string())
+                    .with_span(span)
+                    .with_note("give the variable a type, like `let a : [int] = [];`".to_string()));
+                return None;
+            }
+        };
+        let mut ok = true;
+        for e in rest {
+            match self.value_as(e, &element) {
+                Some(ty) if ty != element => {
+                    self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!("Array elements must be '{}', but this one is '{}'", element, ty))
+                        .with_span(e.span));
+                    ok = false;
+                }
+                Some(_) => {}
+                None => ok = false,
+            }
+        }
+        ok.then(|| Type::Array(Box::new(element)))
+    }
+
+    // checks `array[index]` and returns the element type
+    fn element(&mut self, array : &mut Expr, index : &mut Expr) -> Option<Type> {
+        let array_type = self.value(array);
+        if let Some(ty) = self.value(index) && ty != Type::Int {
+            self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!("Array index must be int, but got '{}'", ty))
+                .with_span(index.span));
+        }
+        let array_type = array_type?;
+        match array_type.element() {
+            Some(element) => Some(element.clone()),
+            None => {
+                self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!("Can't index into a value of type '{}'", array_type))
+                    .with_span(array.span));
+                None
+            }
+        }
+    }
+
+    fn struct_lit(&mut self, name : &Ident, fields : &mut [(Ident, Expr)]) -> Option<Type> {
+        let def = match self.structs.get(&name.name) {
+            Some(def) => def.clone(),
+            None => {
+                for (_, value) in fields.iter_mut() {
+                    self.value(value);
+                }
+                self.report(Diagnostic::error(diagnostic::UNDEFINED_TYPE, format!("Unknown struct '{}'", name.name))
+                    .with_span(name.span));
+                return None;
+            }
+        };
+
+        let mut ok = true;
+        let mut given = vec![false; def.fields.len()];
+        for (field, value) in fields.iter_mut() {
+            let Some((i, field_def)) = def.field(&field.name) else {
+                self.value(value);
+                self.report(Diagnostic::error(diagnostic::UNKNOWN_FIELD, format!("Struct '{}' has no field '{}'", name.name, field.name))
+                    .with_span(field.span));
+                ok = false;
+                continue;
+            };
+            if given[i] {
+                self.report(Diagnostic::error(diagnostic::DUPLICATE_DEFINITION, format!("Field '{}' is given more than once", field.name))
+                    .with_span(field.span));
+                ok = false;
+            }
+            given[i] = true;
+            // the declaration already reported the type
+            if self.unknown_type(&field_def.ty).is_some() {
+                self.value(value);
+                ok = false;
+                continue;
+            }
+            match self.value_as(value, &field_def.ty) {
+                Some(ty) if ty != field_def.ty => {
+                    self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!(
+                        "field '{}' of '{}' expects type '{}', but got '{}'",
+                        field.name, name.name, field_def.ty, ty
+                    )).with_span(value.span));
+                    ok = false;
+                }
+                Some(_) => {}
+                None => ok = false,
+            }
+        }
+
+        let missing : Vec<&str> = def.fields.iter().zip(&given).filter(|(_, given)| !**given).map(|(f, _)| f.name.name.as_str()).collect();
+        if !missing.is_empty() {
+            self.report(Diagnostic::error(diagnostic::MISSING_FIELD, format!("Missing field(s) in '{}': {}", name.name, missing.join(", ")))
+                .with_span(name.span));
+            return None;
+        }
+        if !ok {
+            return None;
+        }
+        // the backends build the fields in declaration order
+        fields.sort_by_key(|(field, _)| def.field(&field.name).map(|(i, _)| i));
+        Some(Type::Named(name.name.clone()))
+    }
+
+    fn builtin(&mut self, name : &Ident, args : &mut [Expr], span : Span) -> Option<Type> {
+        let builtin = Builtin::from_name(&name.name)?;
+        if builtin == Builtin::Format {
+            let format = match args.first().map(|arg| &arg.kind) {
+                Some(ExprKind::Str(format)) => format.clone(),
+                _ => {
+                    let span = args.first().map_or(span, |arg| arg.span);
+                    self.report(Diagnostic::error(diagnostic::PRINT_FORMAT, "First argument to format must be a string literal".to_string())
+                        .with_span(span));
+                    for arg in args.iter_mut() {
+                        self.value(arg);
+                    }
+                    return Some(Type::String);
+                }
+            };
+            let format_span = args[0].span;
+            self.format_args(&format, format_span, &mut args[1..]);
+            return Some(Type::String);
+        }
+        if let Some((params, return_type)) = builtin.signature() {
+            return self.fixed_builtin(name, &params, return_type, args, span);
+        }
+        let (arity, signature) = match builtin {
+            Builtin::Len => (1, "len([T]): int or len(string): int"),
+            Builtin::Push => (2, "push([T], T)"),
+            _ => (1, "pop([T]): T"),
+        };
+        if args.len() != arity {
+            for arg in args.iter_mut() {
+                self.value(arg);
+            }
+            self.report(Diagnostic::error(diagnostic::ARGUMENT_COUNT, format!(
+                "Function '{}' expects {} argument(s), but got {}",
+                name.name, arity, args.len()
+            )).with_span(span).with_note(format!("signature is {}", signature)));
+            return None;
+        }
+
+        let array_type = self.value(&mut args[0]);
+        if builtin == Builtin::Len && array_type == Some(Type::String) {
+            return Some(Type::Int);
+        }
+        let element = match array_type.as_ref().map(|ty| ty.element()) {
+            Some(Some(element)) => Some(element.clone()),
+            Some(None) => {
+                let expected = if builtin == Builtin::Len { "an array or a string" } else { "an array" };
+                self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!(
+                    "argument 1 of '{}' expects {}, but got '{}'",
+                    name.name, expected, array_type.unwrap()
+                )).with_span(args[0].span));
+                None
+            }
+            None => None,
+        };
+        match builtin {
+            Builtin::Len => {
+                element?;
+                Some(Type::Int)
+            }
+            Builtin::Push => {
+                let value_type = match &element {
+                    Some(element) => self.value_as(&mut args[1], element),
+                    None => self.value(&mut args[1]),
+                };
+                let (element, value_type) = (element?, value_type?);
+                if value_type != element {
+                    self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!(
+                        "argument 2 of 'push' expects type '{}', but got '{}'",
+                        element, value_type
+                    )).with_span(args[1].span));
+                }
+                Some(Type::Void)
+            }
+            _ => element,
+        }
+    }
+
+    fn fixed_builtin(&mut self, name : &Ident, params : &[Type], return_type : Type, args : &mut [Expr], span : Span) -> Option<Type> {
+        let arg_types : Vec<Option<Type>> = args.iter_mut().map(|arg| self.value(arg)).collect();
+        if args.len() != params.len() {
+            self.report(Diagnostic::error(diagnostic::ARGUMENT_COUNT, format!(
+                "Function '{}' expects {} argument(s), but got {}",
+                name.name, params.len(), args.len()
+            )).with_span(span).with_note(format!(
+                "signature is {}({}){}",
+                name.name,
+                params.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "),
+                if return_type == Type::Void { String::new() } else { format!(": {}", return_type) }
+            )));
+            return None;
+        }
+        let mut ok = true;
+        for (i, (arg_type, param)) in arg_types.into_iter().zip(params).enumerate() {
+            match arg_type {
+                Some(arg_type) if arg_type != *param => {
+                    self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!(
+                        "argument {} of '{}' expects type '{}', but got '{}'",
+                        i + 1, name.name, param, arg_type
+                    )).with_span(args[i].span));
+                    ok = false;
+                }
+                Some(_) => {}
+                None => ok = false,
+            }
+        }
+        ok.then_some(return_type)
+    }
+
+    fn condition(&mut self, e : &mut Expr) {
+        if let Some(ty) = self.value(e) && ty != Type::Bool {
+            self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!("Condition must be bool, but got '{}'", ty))
+                .with_span(e.span));
+        }
+    }
+
+    // the arguments of print and friends and of format(), checked against the
+    // placeholders of `format`
+    fn format_args(&mut self, format : &str, format_span : Span, args : &mut [Expr]) {
+        let parts = match format::parse(format) {
+            Ok(parts) => parts,
+            Err(message) => {
+                self.report(Diagnostic::error(diagnostic::PRINT_FORMAT, message)
+                    .with_span(format_span)
+                    .with_note("placeholders look like {}, {:8}, {:<8}, {:.2}, {:x}, {:b} or {:e}".to_string()));
+                for arg in args {
+                    self.value(arg);
+                }
+                return;
+            }
+        };
+        let placeholder_count = format::placeholder_count(&parts);
+        if placeholder_count != args.len() {
+            self.report(Diagnostic::error(diagnostic::PRINT_FORMAT, format!(
+                "Mismatched number of placeholders and arguments: expected {}, got {}",
+                placeholder_count,
+                args.len()
+            )).with_span(format_span));
+        }
+        let specs = parts.iter().filter_map(|part| match part {
+            format::Part::Placeholder(spec) => Some(*spec),
+            format::Part::Text(_) => None,
+        });
+        let types : Vec<Option<Type>> = args.iter_mut().map(|arg| self.value(arg)).collect();
+        for ((spec, ty), arg) in specs.zip(types).zip(args.iter()) {
+            if let Some(ty) = ty && let Err(message) = spec.check(&ty) {
+                self.report(Diagnostic::error(diagnostic::PRINT_FORMAT, message).with_span(arg.span));
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt : &mut Stmt) {
+        match &mut stmt.kind {
+            StmtKind::Print { format, format_span, args, .. } => self.format_args(format, *format_span, args),
+            StmtKind::Let { name, declared_type, value, c_name, .. } => {
+                // checked before declaring, so `let x = x + 1;` reads the outer x
+                if let Some(declared) = declared_type && !self.known_type(declared, name.span) {
+                    self.value(value);
+                    *c_name = self.declare(name, Type::Void);
+                    return;
+                }
+                let value_type = match declared_type {
+                    Some(declared) => self.value_as(value, declared),
+                    None => self.value(value),
+                };
+
+                // a variable whose value failed to check is still declared, as Void, so
+                // later uses of it don't report it as undefined
+                let ty = match (declared_type.clone(), value_type) {
+                    (Some(declared), Some(value_type)) if declared != value_type => {
+                        self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!(
+                            "Variable '{}' is declared as '{}', but its value is '{}'",
+                            name.name, declared, value_type
+                        )).with_span(value.span));
+                        declared
+                    }
+                    (Some(declared), _) => declared,
+                    (None, Some(value_type)) => value_type,
+                    (None, None) => Type::Void,
+                };
+                *c_name = self.declare(name, ty);
+            }
+            StmtKind::Assign { name, value, c_name, .. } => {
+                let var_type = match self.lookup(&name.name) {
+                    Some(info) => {
+                        *c_name = info.c_name.clone();
+                        info.ty.clone()
+                    }
+                    None => {
+                        self.value(value);
+                        self.undefined_variable(&name.name, name.span);
+                        return;
+                    }
+                };
+                let value_type = self.value_as(value, &var_type);
+
+                if let Some(value_type) = value_type && var_type != Type::Void && var_type != value_type {
+                    self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!(
+                        "variable '{}' expects type '{}', but got '{}'",
+                        name.name, var_type, value_type
+                    )).with_span(value.span));
+                }
+            }
+            StmtKind::Set { target, value } => {
+                if !is_place(target) {
+                    self.report(Diagnostic::error(diagnostic::NOT_ASSIGNABLE, "Can't assign to a field of a temporary value".to_string())
+                        .with_span(target.span)
+                        .with_note("store it in a variable first".to_string()));
+                }
+                let target_type = self.value(target);
+                let value_type = match &target_type {
+                    Some(target_type) => self.value_as(value, target_type),
+                    None => self.value(value),
+                };
+                if let (Some(target_type), Some(value_type)) = (target_type, value_type) && target_type != value_type {
+                    let what = match &target.kind {
+                        ExprKind::Field { field, .. } => format!("field '{}'", field.name),
+                        _ => "array element".to_string(),
+                    };
+                    self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!(
+                        "{} expects type '{}', but got '{}'",
+                        what, target_type, value_type
+                    )).with_span(value.span));
+                }
+            }
+            StmtKind::If { branches, else_body } => {
+                for (cond, body) in branches {
+                    self.condition(cond);
+                    self.block(body);
+                }
+                if let Some(else_body) = else_body {
+                    self.block(else_body);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                self.condition(cond);
+                self.block(body);
+            }
+            StmtKind::For { var, c_var, start, end, step, body } => {
+                for bound in [start, end] {
+                    if let Some(ty) = self.value(bound) && ty != Type::Int {
+                        self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!("For loop bounds must be int, but got '{}'", ty))
+                            .with_span(bound.span));
+                    }
+                }
+                if let Some(step) = step && let Some(ty) = self.value(step) && ty != Type::Int {
+                    self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!("For loop step must be int, but got '{}'", ty))
+                        .with_span(step.span));
+                }
+
+                // the loop variable lives in its own scope around the body, like in C
+                self.push_scope();
+                *c_var = self.declare(var, Type::Int);
+                self.block(body);
+                self.pop_scope();
+            }
+            StmtKind::Return { value } => {
+                let expected = self.current_return.clone();
+                let value_type = match value {
+                    Some(value) => match self.value_as(value, &expected) {
+                        Some(ty) => ty,
+                        None => return,
+                    },
+                    None => Type::Void,
+                };
+                if value_type != expected {
+                    let span = value.as_ref().map(|v| v.span).unwrap_or(stmt.span);
+                    self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!(
+                        "function returns '{}', but 'return' gives '{}'",
+                        self.current_return, value_type
+                    )).with_span(span));
+                }
+            }
+            StmtKind::Match { scrutinee, arms } => {
+                let def = self.scrutinee(scrutinee);
+                let mut covered = vec![false; def.as_ref().map_or(0, |def| def.variants.len())];
+                for (pattern, body) in arms {
+                    self.push_scope();
+                    self.pattern(def.as_ref(), pattern, &mut covered);
+                    self.block(body);
+                    self.pop_scope();
+                }
+                self.exhaustive(def.as_ref(), &covered, scrutinee.span);
+            }
+            StmtKind::Expr(e) => {
+                self.expr(e);
+            }
+        }
+    }
+
+    // the enum a match is on, or None after reporting why there isn't one
+    fn scrutinee(&mut self, e : &mut Expr) -> Option<EnumDef> {
+        let ty = self.value(e)?;
+        if let Type::Named(name) = &ty && let Some(def) = self.enums.get(name) {
+            return Some(def.clone());
+        }
+        if let Type::Optional(inner) = &ty {
+            return Some(EnumDef::optional(inner));
+        }
+        self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!("Can't match on a value of type '{}'", ty))
+            .with_span(e.span)
+            .with_note("match works on enums and optional values".to_string()));
+        None
+    }
+
+    // resolves the variant of one arm and declares its bindings in the current
+    // scope. `covered` has an entry per variant of `def`
+    fn pattern(&mut self, def : Option<&EnumDef>, pattern : &mut Pattern, covered : &mut [bool]) {
+        let span = pattern.span();
+        if !covered.is_empty() && covered.iter().all(|c| *c) {
+            self.report(Diagnostic::error(diagnostic::UNREACHABLE_ARM, "This arm can never match".to_string())
+                .with_span(span)
+                .with_note("the arms above already cover every variant".to_string()));
+        }
+        let Pattern::Variant { enum_name, variant, bindings, index } = pattern else {
+            covered.fill(true);
+            return;
+        };
+
+        let mut payload = None;
+        if let Some(def) = def {
+            if let Some(enum_name) = enum_name && enum_name.name != def.name.name {
+                self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!("Expected a variant of '{}', but found '{}'", def.name.name, enum_name.name))
+                    .with_span(enum_name.span));
+            } else {
+                match def.variant(&variant.name) {
+                    Some((i, variant_def)) => {
+                        if covered[i] && !covered.iter().all(|c| *c) {
+                            self.report(Diagnostic::error(diagnostic::UNREACHABLE_ARM, format!("Variant '{}' is already matched above", variant.name))
+                                .with_span(span));
+                        }
+                        covered[i] = true;
+                        *index = i;
+                        payload = Some(variant_def.payload.clone());
+                    }
+                    None => {
+                        self.report(Diagnostic::error(diagnostic::UNKNOWN_VARIANT, format!("Enum '{}' has no variant '{}'", def.name.name, variant.name))
+                            .with_span(variant.span));
+                    }
+                }
+            }
+        }
+        if let Some(payload) = &payload && payload.len() != bindings.len() {
+            self.report(Diagnostic::error(diagnostic::ARGUMENT_COUNT, format!(
+                "Variant '{}' holds {} value(s), but the pattern has {}",
+                variant.name, payload.len(), bindings.len()
+            )).with_span(span));
+        }
+
+        // bindings of a pattern that failed to check are Void, like failed lets
+        for (i, binding) in bindings.iter_mut().enumerate() {
+            binding.ty = payload.as_ref().and_then(|payload| payload.get(i)).cloned().unwrap_or(Type::Void);
+            if binding.name.name != "_" {
+                binding.c_name = self.declare(&binding.name, binding.ty.clone());
+            }
+        }
+    }
+
+    fn exhaustive(&mut self, def : Option<&EnumDef>, covered : &[bool], span : Span) {
+        let Some(def) = def else {
+            return;
+        };
+        let missing : Vec<&str> = def.variants.iter().zip(covered).filter(|(_, c)| !**c).map(|(v, _)| v.name.name.as_str()).collect();
+        if !missing.is_empty() {
+            self.report(Diagnostic::error(diagnostic::NON_EXHAUSTIVE, format!("Match on '{}' doesn't cover {}", def.name.name, missing.join(", ")))
+                .with_span(span)
+                .with_note("add arms for them, or a `_` arm".to_string()));
+        }
+    }
+
+    fn variant(&mut self, enum_name : &Ident, variant : &Ident, args : &mut [Expr], index : &mut usize, span : Span) -> Option<Type> {
+        let def = self.enums.get(&enum_name.name).cloned();
+        let found = def.as_ref().and_then(|def| def.variant(&variant.name));
+        let Some((i, variant_def)) = found else {
+            for arg in args.iter_mut() {
+                self.value(arg);
+            }
+            match def {
+                Some(_) => self.report(Diagnostic::error(diagnostic::UNKNOWN_VARIANT, format!("Enum '{}' has no variant '{}'", enum_name.name, variant.name))
+                    .with_span(variant.span)),
+                None => self.report(Diagnostic::error(diagnostic::UNDEFINED_TYPE, format!("Unknown enum '{}'", enum_name.name))
+                    .with_span(enum_name.span)),
+            }
+            return None;
+        };
+        *index = i;
+
+        let mut ok = true;
+        for (arg, ty) in args.iter_mut().zip(&variant_def.payload) {
+            match self.value_as(arg, ty) {
+                Some(arg_type) if arg_type != *ty => {
+                    self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!(
+                        "'{}::{}' holds '{}', but got '{}'",
+                        enum_name.name, variant.name, ty, arg_type
+                    )).with_span(arg.span));
+                    ok = false;
+                }
+                Some(_) => {}
+                None => ok = false,
+            }
+        }
+        for arg in args.iter_mut().skip(variant_def.payload.len()) {
+            self.value(arg);
+        }
+        if args.len() != variant_def.payload.len() {
+            self.report(Diagnostic::error(diagnostic::ARGUMENT_COUNT, format!(
+                "Variant '{}' holds {} value(s), but got {}",
+                variant.name, variant_def.payload.len(), args.len()
+            )).with_span(span));
+            return None;
+        }
+        ok.then(|| Type::Named(enum_name.name.clone()))
+    }
+
+    // the arms of a match expression have to agree on one type
+    fn match_expr(&mut self, scrutinee : &mut Expr, arms : &mut [(Pattern, Expr)], span : Span) -> Option<Type> {
+        let def = self.scrutinee(scrutinee);
+        let mut covered = vec![false; def.as_ref().map_or(0, |def| def.variants.len())];
+        let mut ty : Option<Type> = None;
+        let mut ok = def.is_some();
+        for (pattern, value) in arms.iter_mut() {
+            self.push_scope();
+            self.pattern(def.as_ref(), pattern, &mut covered);
+            let value_type = match &ty {
+                Some(expected) => self.value_as(value, expected),
+                None => self.value(value),
+            };
+            self.pop_scope();
+            match (&ty, value_type) {
+                (Some(expected), Some(value_type)) if *expected != value_type => {
+                    self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!(
+                        "match arms must all be '{}', but this one is '{}'",
+                        expected, value_type
+                    )).with_span(value.span));
+                    ok = false;
+                }
+                (None, Some(value_type)) => ty = Some(value_type),
+                (_, None) => ok = false,
+                _ => {}
+            }
+        }
+        self.exhaustive(def.as_ref(), &covered, scrutinee.span);
+        if arms.is_empty() {
+            self.report(Diagnostic::error(diagnostic::NON_EXHAUSTIVE, "A match expression needs at least one arm".to_string())
+                .with_span(span));
+        }
+        if ok && covered.iter().all(|c| *c) { ty } else { None }
+    }
+
+    // returns the expression's type, or None when an error was already reported
+    // for it so callers don't pile more errors on top
+    fn expr(&mut self, e : &mut Expr) -> Option<Type> {
+        let ty = match &mut e.kind {
+            ExprKind::Int(_) => Type::Int,
+            ExprKind::Float(_) => Type::Float,
+            ExprKind::Str(_) => Type::String,
+            ExprKind::Bool(_) => Type::Bool,
+            ExprKind::Var { name, c_name, .. } => {
+                let info = match self.lookup(name) {
+                    Some(info) => info,
+                    None => {
+                        let name = name.clone();
+                        self.undefined_variable(&name, e.span);
+                        return None;
+                    }
+                };
+                *c_name = info.c_name.clone();
+                if info.ty == Type::Void {
+                    return None;
+                }
+                info.ty.clone()
+            }
+            ExprKind::Array(elements) => self.array_literal(elements, e.span, None)?,
+            ExprKind::Index { array, index } => self.element(array, index)?,
+            ExprKind::StructLit { name, fields } => self.struct_lit(name, fields)?,
+            ExprKind::Variant { enum_name, variant, args, index } => self.variant(enum_name, variant, args, index, e.span)?,
+            ExprKind::Match { scrutinee, arms, id } => {
+                self.match_count += 1;
+                *id = self.match_count;
+                self.match_expr(scrutinee, arms, e.span)?
+            }
+            ExprKind::Field { base, field, index } => {
+                let base_type = self.value(base)?;
+                let def = match &base_type {
+                    Type::Named(name) => self.structs.get(name),
+                    _ => None,
+                };
+                match def.and_then(|def| def.field(&field.name)) {
+                    Some((i, field_def)) => {
+                        *index = i;
+                        field_def.ty.clone()
+                    }
+                    None => {
+                        let message = match &base_type {
+                            Type::Named(name) if self.structs.contains_key(name) => format!("Struct '{}' has no field '{}'", name, field.name),
+                            _ => format!("Type '{}' has no fields", base_type),
+                        };
+                        self.report(Diagnostic::error(diagnostic::UNKNOWN_FIELD, message).with_span(field.span));
+                        return None;
+                    }
+                }
+            }
+            ExprKind::Unary { op, op_span, operand } => {
+                let operand_type = self.value(operand)?;
+                let ok = match op {
+                    UnaryOp::Not => operand_type == Type::Bool,
+                    UnaryOp::Neg => operand_type.is_numeric(),
+                };
+                if !ok {
+                    self.report(Diagnostic::error(diagnostic::INVALID_OPERATOR, format!("Operator '{}' can't be applied to '{}'", op.symbol(), operand_type))
+                        .with_span(*op_span));
+                    return None;
+                }
+                operand_type
+            }
+            ExprKind::Binary { op, op_span, left, right } => {
+                let l = self.value(left);
+                let r = self.value(right);
+                let (l, r) = (l?, r?);
+
+                let accepts = |t : &Type| match op {
+                    BinOp::And | BinOp::Or => *t == Type::Bool,
+                    BinOp::Eq | BinOp::Ne => !matches!(t, Type::Array(_) | Type::Named(_) | Type::Optional(_)),
+                    BinOp::Mod => *t == Type::Int,
+                    BinOp::Add => t.is_numeric() || *t == Type::String,
+                    BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => t.is_numeric() || *t == Type::String,
+                    _ => t.is_numeric(),
+                };
+                let same_kind = l == r || (l.is_numeric() && r.is_numeric());
+                if !same_kind {
+                    self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!("Mismatched operands: left is '{}' and right is '{}'", l, r))
+                        .with_span(e.span));
+                    return None;
+                }
+                if !accepts(&l) || !accepts(&r) {
+                    let bad = if accepts(&l) { r } else { l };
+                    let mut d = Diagnostic::error(diagnostic::INVALID_OPERATOR, format!("Operator '{}' can't be applied to '{}'", op.symbol(), bad))
+                        .with_span(*op_span);
+                    if bad == Type::String {
+                        d = d.with_note("strings only support + and comparisons".to_string());
+                    }
+                    self.report(d);
+                    return None;
+                }
+
+                if op.is_comparison() || op.is_logical() {
+                    Type::Bool
+                } else if l == Type::String {
+                    Type::String
+                } else if l == Type::Float || r == Type::Float {
+                    Type::Float
+                } else {
+                    Type::Int
+                }
+            }
+            ExprKind::Call { name, args } if Builtin::from_name(&name.name).is_some() => self.builtin(name, args, e.span)?,
+            ExprKind::Call { name, args } => {
+                let info = match self.functions.get(&name.name) {
+                    Some(info) => info.clone(),
+                    None => {
+                        for arg in args.iter_mut() {
+                            self.value(arg);
+                        }
+                        self.report(Diagnostic::error(diagnostic::UNDEFINED_FUNCTION, format!("Undefined function: {}", name.name))
+                            .with_span(name.span));
+                        return None;
+                    }
+                };
+                let arg_types : Vec<Option<Type>> = args.iter_mut().enumerate().map(|(i, arg)| match info.params.get(i) {
+                    Some(param_type) => self.value_as(arg, param_type),
+                    None => self.value(arg),
+                }).collect();
+
+                if args.len() != info.params.len() {
+                    self.report(Diagnostic::error(diagnostic::ARGUMENT_COUNT, format!(
+                        "Function '{}' expects {} argument(s), but got {}",
+                        name.name, info.params.len(), args.len()
+                    )).with_span(e.span).with_note(format!(
+                        "signature is {}({})",
+                        name.name, info.params.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ")
+                    )));
+                } else {
+                    for (i, (arg, param_type)) in args.iter().zip(info.params.iter()).enumerate() {
+                        if let Some(arg_type) = &arg_types[i] && arg_type != param_type {
+                            self.report(Diagnostic::error(diagnostic::TYPE_MISMATCH, format!(
+                                "argument {} of '{}' expects type '{}', but got '{}'",
+                                i + 1, name.name, param_type, arg_type
+                            )).with_span(arg.span));
+                        }
+                    }
+                }
+                info.return_type
+            }
+        };
+        e.ty = ty.clone();
+        Some(ty)
+    }
+}
+
+// whether `p.x = v` can write into e; a[i] always can, since arrays are shared
+fn is_place(e : &Expr) -> bool {
+    match &e.kind {
+        ExprKind::Var { .. } | ExprKind::Index { .. } => true,
+        ExprKind::Field { base, .. } => is_place(base),
+        _ => false,
+    }
+}
+
+// whether running `stmts` always ends in a return. Loops don't count, even
+// `while true`, and an if needs an else
+fn always_returns(stmts : &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match &stmt.kind {
+        StmtKind::Return { .. } => true,
+        StmtKind::If { branches, else_body : Some(else_body) } => {
+            branches.iter().all(|(_, body)| always_returns(body)) && always_returns(else_body)
+        }
+        StmtKind::Match { arms, .. } => !arms.is_empty() && arms.iter().all(|(_, body)| always_returns(body)),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver;
+    use crate::source::SourceFile;
+
+    const SHAPE : &str = "enum Shape { Circle(float), Square(float), Empty }\n";
+
+    // the codes of the errors the checker reports for `main`'s body
+    fn errors(body : &str) -> Vec<&'static str> {
+        program_errors(&format!("{}fun main() {{\n{}\n}}\n", SHAPE, body))
+    }
+
+    fn program_errors(text : &str) -> Vec<&'static str> {
+        let source = SourceFile::new("test.fun", text.to_string());
+        let mut program = driver::parse(&source).expect("test programs parse");
+        match check_program(&mut program) {
+            Ok(()) => vec![],
+            Err(diagnostics) => diagnostics.iter().map(|d| d.code).collect(),
+        }
+    }
+
+    #[test]
+    fn accepts_exhaustive_matches() {
+        assert_eq!(errors("let s = Shape::Empty;\nmatch s {\nShape::Circle(r) => {}\nShape::Square(w) => {}\nShape::Empty => {}\n}"), Vec::<&str>::new());
+        assert_eq!(errors("let s = Shape::Empty;\nmatch s {\nShape::Circle(r) => {}\n_ => {}\n}"), Vec::<&str>::new());
+        assert_eq!(errors("let s = Shape::Empty;\nlet n = match s { Shape::Empty => 0, _ => 1 };"), Vec::<&str>::new());
+        assert_eq!(errors("match try_read_int() {\nSome(n) => {}\nNone => {}\n}"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn reports_missing_variants() {
+        assert_eq!(errors("let s = Shape::Empty;\nmatch s {\nShape::Circle(r) => {}\nShape::Empty => {}\n}"), vec![diagnostic::NON_EXHAUSTIVE]);
+        assert_eq!(errors("let s = Shape::Empty;\nlet n = match s { Shape::Empty => 0 };"), vec![diagnostic::NON_EXHAUSTIVE]);
+        assert_eq!(errors("match try_read_line() {\nSome(line) => {}\n}"), vec![diagnostic::NON_EXHAUSTIVE]);
+    }
+
+    #[test]
+    fn reports_unreachable_arms() {
+        assert_eq!(errors("let s = Shape::Empty;\nmatch s {\nShape::Empty => {}\nShape::Empty => {}\n_ => {}\n}"), vec![diagnostic::UNREACHABLE_ARM]);
+        assert_eq!(errors("let s = Shape::Empty;\nmatch s {\n_ => {}\nShape::Empty => {}\n}"), vec![diagnostic::UNREACHABLE_ARM]);
+        assert_eq!(errors("let s = Shape::Empty;\nlet n = match s { Shape::Circle(r) => 1, Shape::Square(w) => 2, Shape::Empty => 3, _ => 4 };"), vec![diagnostic::UNREACHABLE_ARM]);
+    }
+
+    #[test]
+    fn conditions_must_be_bool() {
+        assert_eq!(errors("if 1 {\n}"), vec![diagnostic::TYPE_MISMATCH]);
+        assert_eq!(errors("if true {\n} elf \"x\" {\n}"), vec![diagnostic::TYPE_MISMATCH]);
+        assert_eq!(errors("while 1.5 {\n}"), vec![diagnostic::TYPE_MISMATCH]);
+        assert_eq!(errors("let n = 1;\nwhile n {\n}"), vec![diagnostic::TYPE_MISMATCH]);
+        assert_eq!(errors("let b = !1;"), vec![diagnostic::INVALID_OPERATOR]);
+        assert_eq!(errors("let b : int = 1 == 2;"), vec![diagnostic::TYPE_MISMATCH]);
+        assert_eq!(errors("let b = 1 < 2;\nif b || false {\n} elf !b && 2.0 >= 1.5 {\n}\nwhile false {\n}"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn scopes_end_with_their_block() {
+        assert_eq!(errors("if true {\nlet x = 1;\n}\nprintln(\"{}\", x);"), vec![diagnostic::UNDEFINED_VARIABLE]);
+        assert_eq!(errors("for 0:3 i {\n}\nprintln(\"{}\", i);"), vec![diagnostic::UNDEFINED_VARIABLE]);
+        assert_eq!(errors("let x = 1;\nif true {\nlet x = \"shadowed\";\nprintln(\"{}\", len(x));\n}\nlet y : int = x;"), Vec::<&str>::new());
+        assert_eq!(errors("let x = 1;\nlet x = \"again\";\nlet y : int = x;"), vec![diagnostic::TYPE_MISMATCH]);
+    }
+
+    #[test]
+    fn requires_main() {
+        assert_eq!(program_errors("fun helper() : int {\nreturn 1;\n}\n"), vec![diagnostic::MISSING_MAIN]);
+        assert_eq!(program_errors("fun main() {\n}\n"), Vec::<&str>::new());
+    }
+}
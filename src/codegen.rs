@@ -0,0 +1,919 @@
+use std::collections::{HashMap,HashSet};
+use std::mem;
+
+use crate::format::{self,Kind,Part,Spec};
+use crate::nodes::*;
+use crate::source::Span;
+use crate::visit::{self,Visitor};
+
+pub fn c_type_name(ty : &Type) -> String {
+    match ty {
+        Type::Int => "int".to_string(),
+        Type::Float => "float".to_string(),
+        Type::String => "char*".to_string(),
+        Type::Bool => "bool".to_string(),
+        Type::Array(_) => "fl_array*".to_string(),
+        Type::Named(name) => user_name(name),
+        Type::Optional(inner) => format!("fl_opt_{}", inner),
+        Type::Void => "void".to_string(),
+    }
+}
+
+// the C name of a function, type, field, variant or variable of the program.
+// The prefix keeps `fun abs`, `let double`, `struct FILE` and names like
+// fl_concat apart from C keywords, the C library and the runtime
+pub fn user_name(name : &str) -> String {
+    format!("u_{}", name)
+}
+
+// escapes `s` for use inside a C string literal; anything that is not printable
+// ASCII is written as octal bytes so the C compiler sees exactly the same bytes
+pub fn c_escape(s : &str) -> String {
+    let mut out = String::new();
+    let mut prev = '\0';
+    for ch in s.chars() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '?' if prev == '?' => out.push_str("\\?"), // no trigraphs
+            ' '..='~' => out.push(ch),
+            _ => {
+                let mut buf = [0u8; 4];
+                for byte in ch.encode_utf8(&mut buf).bytes() {
+                    out.push_str(&format!("\\{:03o}", byte));
+                }
+            }
+        }
+        prev = ch;
+    }
+    out
+}
+
+pub fn c_string_literal(s : &str) -> String {
+    format!("\"{}\"", c_escape(s))
+}
+
+// bools are printed as "true"/"false" through %s, see print
+pub fn c_format_of(ty : &Type) -> &'static str {
+    match ty {
+        Type::Int => "%d",
+        Type::Float => "%f",
+        _ => "%s",
+    }
+}
+
+// expects a program that passed the checker
+pub fn generate(program : &Program) -> String {
+    let mut out = String::new();
+    let types_by_name = Types::new(program);
+    out.push_str(&types(program, &types_by_name));
+    out.push_str(&printers(program, &types_by_name));
+    for f in &program.functions {
+        out.push_str(&prototype(f));
+    }
+    if !program.functions.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(&match_functions(program));
+    for f in &program.functions {
+        out.push_str(&function(f));
+        out.push('\n');
+    }
+    if let Some(main) = &program.main {
+        let body = block(&main.body, true);
+        out.push_str(&format!("int main() {{\nfl_init();\n{}return 0;\n}}\n", body));
+    }
+    out
+}
+
+// the declared structs and enums, by name
+struct Types<'a> {
+    structs : HashMap<&'a str,&'a StructDef>,
+    enums : HashMap<&'a str,&'a EnumDef>,
+}
+
+impl<'a> Types<'a> {
+    fn new(program : &'a Program) -> Self {
+        Self {
+            structs : program.structs.iter().map(|def| (def.name.name.as_str(), def)).collect(),
+            enums : program.enums.iter().map(|def| (def.name.name.as_str(), def)).collect(),
+        }
+    }
+
+    // the types a struct or enum holds by value
+    fn members(&self, name : &str) -> Vec<&'a Type> {
+        match self.structs.get(name) {
+            Some(def) => def.fields.iter().map(|field| &field.ty).collect(),
+            None => self.enums[name].variants.iter().flat_map(|variant| &variant.payload).collect(),
+        }
+    }
+}
+
+// typedefs for every struct and enum first, so members can name types declared
+// later, then the definitions with each type after the ones it holds by value
+fn types(program : &Program, types : &Types) -> String {
+    let names : Vec<&str> = program.structs.iter().map(|def| def.name.name.as_str())
+        .chain(program.enums.iter().map(|def| def.name.name.as_str()))
+        .collect();
+    let mut out = String::new();
+    for name in &names {
+        out.push_str(&format!("typedef struct {0} {0};\n", user_name(name)));
+    }
+    if !names.is_empty() {
+        out.push('\n');
+    }
+    let mut done = HashSet::new();
+    for name in names {
+        type_definition(name, types, &mut done, &mut out);
+    }
+    out
+}
+
+fn type_definition<'a>(name : &'a str, types : &Types<'a>, done : &mut HashSet<&'a str>, out : &mut String) {
+    if !done.insert(name) {
+        return;
+    }
+    for ty in types.members(name) {
+        if let Type::Named(member) = ty {
+            type_definition(member, types, done, out);
+        }
+    }
+    out.push_str(&format!("struct {} {{\n", user_name(name)));
+    if let Some(def) = types.structs.get(name) {
+        for field in &def.fields {
+            out.push_str(&format!("{} {};\n", c_type_name(&field.ty), user_name(&field.name.name)));
+        }
+    } else {
+        // a tag, which is the variant's position, and a struct per variant with a payload
+        out.push_str("int tag;\n");
+        let variants : Vec<&VariantDef> = types.enums[name].variants.iter().filter(|variant| !variant.payload.is_empty()).collect();
+        if !variants.is_empty() {
+            out.push_str("union {\n");
+            for variant in variants {
+                out.push_str("struct {\n");
+                for (i, ty) in variant.payload.iter().enumerate() {
+                    out.push_str(&format!("{} _{};\n", c_type_name(ty), i));
+                }
+                out.push_str(&format!("}} {};\n", user_name(&variant.name.name)));
+            }
+            out.push_str("} as;\n");
+        }
+    }
+    out.push_str("};\n\n");
+}
+
+fn is_composite(ty : &Type) -> bool {
+    matches!(ty, Type::Array(_) | Type::Named(_) | Type::Optional(_))
+}
+
+// name of the function that prints a value of type `ty`, for arrays, structs
+// and enums. The prefixes keep [int] and a struct called array_int apart
+fn printer_name(ty : &Type) -> String {
+    fn mangle(ty : &Type) -> String {
+        match ty {
+            Type::Array(element) => format!("array_{}", mangle(element)),
+            Type::Named(name) => format!("named_{}", name),
+            Type::Optional(inner) => format!("opt_{}", mangle(inner)),
+            ty => ty.to_string(),
+        }
+    }
+    format!("fl_print_{}", mangle(ty))
+}
+
+// the composite types some print statement or format() needs, with the ones
+// they contain
+struct Printed<'a, 't> {
+    types : &'t Types<'a>,
+    printed : Vec<Type>,
+}
+
+impl Printed<'_, '_> {
+    fn add(&mut self, ty : &Type) {
+        if !is_composite(ty) || self.printed.contains(ty) {
+            return;
+        }
+        self.printed.push(ty.clone());
+        match ty {
+            Type::Array(element) => self.add(element),
+            Type::Named(name) => {
+                for member in self.types.members(name) {
+                    self.add(member);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Visitor for Printed<'_, '_> {
+    fn visit_stmt(&mut self, stmt : &Stmt) {
+        if let StmtKind::Print { args, .. } = &stmt.kind {
+            for arg in args {
+                self.add(&arg.ty);
+            }
+        }
+        visit::walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, e : &Expr) {
+        if let ExprKind::Call { name, args } = &e.kind && Builtin::from_name(&name.name) == Some(Builtin::Format) {
+            for arg in &args[1..] {
+                self.add(&arg.ty);
+            }
+        }
+        visit::walk_expr(self, e);
+    }
+}
+
+fn printers(program : &Program, types : &Types) -> String {
+    let mut printed = Printed { types, printed : vec![] };
+    printed.visit_program(program);
+    if printed.printed.is_empty() {
+        return String::new();
+    }
+
+    let mut out = String::new();
+    for ty in &printed.printed {
+        out.push_str(&format!("static fl_out* {}(fl_out* out, {} v);\n", printer_name(ty), c_type_name(ty)));
+    }
+    out.push('\n');
+    for ty in &printed.printed {
+        out.push_str(&format!("static fl_out* {}(fl_out* out, {} v) {{\n", printer_name(ty), c_type_name(ty)));
+        match ty {
+            Type::Array(element) => {
+                out.push_str("fl_write(out, \"[\");\nfor (int i = 0; i < v->len; i++) {\nif (i > 0) {\nfl_write(out, \", \");\n}\n");
+                out.push_str(&print_item(&format!("(({}*) v->data)[i]", c_type_name(element)), element));
+                out.push_str("}\nfl_write(out, \"]\");\n");
+            }
+            Type::Named(name) if let Some(def) = types.structs.get(name.as_str()) => {
+                for (i, field) in def.fields.iter().enumerate() {
+                    let label = format!("{}{}: ", if i == 0 { format!("{} {{ ", name) } else { ", ".to_string() }, field.name.name);
+                    out.push_str(&format!("fl_write(out, \"{}\");\n", c_escape(&label)));
+                    out.push_str(&print_item(&format!("v.{}", user_name(&field.name.name)), &field.ty));
+                }
+                out.push_str("fl_write(out, \" }\");\n");
+            }
+            Type::Named(name) => out.push_str(&print_variants(ty, types.enums[name.as_str()])),
+            Type::Optional(inner) => out.push_str(&print_variants(ty, &EnumDef::optional(inner))),
+            _ => {}
+        }
+        out.push_str("return out;\n}\n\n");
+    }
+    out
+}
+
+// the union member that holds a variant's payload. The runtime declares the
+// optionals, so Some keeps its name there
+fn variant_member(ty : &Type, variant : &str) -> String {
+    match ty {
+        Type::Optional(_) => variant.to_string(),
+        _ => user_name(variant),
+    }
+}
+
+fn print_variants(enum_ty : &Type, def : &EnumDef) -> String {
+    let mut out = "switch (v.tag) {\n".to_string();
+    for (tag, variant) in def.variants.iter().enumerate() {
+        out.push_str(&format!("case {}:\nfl_write(out, \"{}\");\n", tag, c_escape(&variant.name.name)));
+        if !variant.payload.is_empty() {
+            out.push_str("fl_write(out, \"(\");\n");
+            for (i, ty) in variant.payload.iter().enumerate() {
+                if i > 0 {
+                    out.push_str("fl_write(out, \", \");\n");
+                }
+                out.push_str(&print_item(&format!("v.as.{}._{}", variant_member(enum_ty, &variant.name.name), i), ty));
+            }
+            out.push_str("fl_write(out, \")\");\n");
+        }
+        out.push_str("break;\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+// one element, field or payload inside a container; strings are quoted there
+fn print_item(value : &str, ty : &Type) -> String {
+    match ty {
+        Type::String => format!("fl_write(out, \"\\\"%s\\\"\", {});\n", value),
+        Type::Bool => format!("fl_write(out, \"%s\", {} ? \"true\" : \"false\");\n", value),
+        ty if is_composite(ty) => format!("{}(out, {});\n", printer_name(ty), value),
+        ty => format!("fl_write(out, \"{}\", {});\n", c_format_of(ty), value),
+    }
+}
+
+// C has no expressions that contain statements, so every match expression
+// becomes a function, fl_match_<id>, that gets the value being matched and
+// the variables its arms read
+struct MatchExprs {
+    found : Vec<Expr>,
+}
+
+impl Visitor for MatchExprs {
+    fn visit_expr(&mut self, expr : &Expr) {
+        if let ExprKind::Match { .. } = &expr.kind {
+            self.found.push(expr.clone());
+        }
+        visit::walk_expr(self, expr);
+    }
+}
+
+// the variables a match expression's arms read that aren't bound inside it,
+// as (c_name, parameter type)
+#[derive(Default)]
+struct FreeVars {
+    read : Vec<(String,String)>,
+    bound : HashSet<String>,
+}
+
+impl Visitor for FreeVars {
+    fn visit_expr(&mut self, expr : &Expr) {
+        match &expr.kind {
+            ExprKind::Var { c_name, .. } => {
+                if !self.read.iter().any(|(name, _)| name == c_name) {
+                    self.read.push((c_name.clone(), c_type_name(&expr.ty)));
+                }
+            }
+            ExprKind::Match { arms, .. } => {
+                for (pattern, _) in arms {
+                    if let Pattern::Variant { bindings, .. } = pattern {
+                        self.bound.extend(bindings.iter().map(|binding| binding.c_name.clone()));
+                    }
+                }
+                visit::walk_expr(self, expr);
+            }
+            _ => visit::walk_expr(self, expr),
+        }
+    }
+}
+
+fn free_vars(arms : &[(Pattern, Expr)]) -> Vec<(String,String)> {
+    let mut free = FreeVars::default();
+    for (pattern, value) in arms {
+        if let Pattern::Variant { bindings, .. } = pattern {
+            free.bound.extend(bindings.iter().map(|binding| binding.c_name.clone()));
+        }
+        free.visit_expr(value);
+    }
+    let bound = free.bound;
+    free.read.into_iter().filter(|(name, _)| !bound.contains(name)).collect()
+}
+
+fn match_functions(program : &Program) -> String {
+    let mut matches = MatchExprs { found : vec![] };
+    matches.visit_program(program);
+    if matches.found.is_empty() {
+        return String::new();
+    }
+
+    let mut signatures = vec![];
+    for e in &matches.found {
+        let ExprKind::Match { scrutinee, arms, id } = &e.kind else {
+            continue;
+        };
+        let mut params = vec![format!("{} fl_match", c_type_name(&scrutinee.ty))];
+        params.extend(free_vars(arms).into_iter().map(|(name, c_type)| format!("{} {}", c_type, name)));
+        signatures.push(format!("static {} fl_match_{}({})", c_type_name(&e.ty), id, params.join(", ")));
+    }
+
+    let mut out = String::new();
+    for signature in &signatures {
+        out.push_str(&format!("{};\n", signature));
+    }
+    out.push('\n');
+    for (e, signature) in matches.found.iter().zip(&signatures) {
+        let ExprKind::Match { scrutinee, arms, .. } = &e.kind else {
+            continue;
+        };
+        let arms : Vec<(&Pattern, String)> = arms.iter()
+            .map(|(pattern, value)| (pattern, format!("{}return {};\n", temporaries(|t| t.visit_expr(value)), expr(value))))
+            .collect();
+        out.push_str(&format!("{} {{\n{}}}\n\n", signature, switch(&scrutinee.ty, &arms)));
+    }
+    out
+}
+
+// a switch over fl_match with one case per arm. The checker made sure the arms
+// cover every variant, so the last one can be the default
+fn switch(ty : &Type, arms : &[(&Pattern, String)]) -> String {
+    let mut out = "switch (fl_match.tag) {\n".to_string();
+    for (i, (pattern, body)) in arms.iter().enumerate() {
+        let mut bindings_code = String::new();
+        if let Pattern::Variant { variant, bindings, .. } = pattern {
+            for (j, binding) in bindings.iter().enumerate() {
+                if binding.name.name != "_" {
+                    bindings_code.push_str(&format!("{} {} = fl_match.as.{}._{};\n", c_type_name(&binding.ty), binding.c_name, variant_member(ty, &variant.name), j));
+                }
+            }
+        }
+        let label = match pattern {
+            _ if i == arms.len() - 1 => "default".to_string(),
+            Pattern::Variant { index, .. } => format!("case {}", index),
+            Pattern::Wildcard(_) => "default".to_string(),
+        };
+        out.push_str(&format!("{}: {{\n{}{}break;\n}}\n", label, bindings_code, body));
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn signature(f : &Function) -> String {
+    let params = if f.params.is_empty() {
+        "void".to_string()
+    } else {
+        f.params.iter().map(|p| format!("{} {}", c_type_name(&p.ty), p.c_name)).collect::<Vec<_>>().join(", ")
+    };
+    format!("{} {}({})", c_type_name(&f.return_type), user_name(&f.name.name), params)
+}
+
+fn prototype(f : &Function) -> String {
+    format!("{};\n", signature(f))
+}
+
+fn function(f : &Function) -> String {
+    format!("{} {{\n{}}}\n", signature(f), block(&f.body, false))
+}
+
+fn block(stmts : &[Stmt], in_main : bool) -> String {
+    let mut out = String::new();
+    for s in stmts {
+        out.push_str(&temporaries(|t| t.visit_stmt(s)));
+        out.push_str(&stmt(s, in_main));
+    }
+    out
+}
+
+// the line and column arguments of the runtime functions that can fail
+fn location(e : &Expr) -> String {
+    format!("{}, {}", e.span.line, e.span.col)
+}
+
+// C leaves the order in which the arguments of a call, the operands of an
+// operator and the items of a literal are evaluated unspecified, but FunLang
+// goes left to right. Where that can show, because one of them has side
+// effects, each one that isn't a constant is evaluated into a temporary first:
+// `(fl_seq_4_21_0 = read_int(), fl_seq_4_21_1 = read_int(), f(fl_seq_4_21_0, fl_seq_4_21_1))`.
+// A temporary is named after the span of the expression it belongs to, and is
+// declared before the statement that contains it
+fn operands(e : &Expr) -> Vec<&Expr> {
+    match &e.kind {
+        ExprKind::Array(items) | ExprKind::Variant { args : items, .. } | ExprKind::Call { args : items, .. } => items.iter().collect(),
+        ExprKind::StructLit { fields, .. } => fields.iter().map(|(_, value)| value).collect(),
+        ExprKind::Index { array, index } => vec![array, index],
+        // && and || already go left to right
+        ExprKind::Binary { op : BinOp::And | BinOp::Or, .. } => vec![],
+        ExprKind::Binary { left, right, .. } => vec![left, right],
+        _ => vec![],
+    }
+}
+
+fn operands_mut(e : &mut Expr) -> Vec<&mut Expr> {
+    match &mut e.kind {
+        ExprKind::Array(items) | ExprKind::Variant { args : items, .. } | ExprKind::Call { args : items, .. } => items.iter_mut().collect(),
+        ExprKind::StructLit { fields, .. } => fields.iter_mut().map(|(_, value)| value).collect(),
+        ExprKind::Index { array, index } => vec![array, index],
+        ExprKind::Binary { op : BinOp::And | BinOp::Or, .. } => vec![],
+        ExprKind::Binary { left, right, .. } => vec![left, right],
+        _ => vec![],
+    }
+}
+
+fn is_constant(e : &Expr) -> bool {
+    match &e.kind {
+        ExprKind::Int(_) | ExprKind::Float(_) | ExprKind::Str(_) | ExprKind::Bool(_) => true,
+        ExprKind::Unary { operand, .. } => is_constant(operand),
+        _ => false,
+    }
+}
+
+// calls, and what can fail with a runtime error
+#[derive(Default)]
+struct Effects {
+    found : bool,
+}
+
+impl Visitor for Effects {
+    fn visit_expr(&mut self, expr : &Expr) {
+        match &expr.kind {
+            ExprKind::Call { name, .. } => match Builtin::from_name(&name.name) {
+                Some(Builtin::Len | Builtin::Find | Builtin::Replace | Builtin::Trim | Builtin::ToUpper | Builtin::ToLower
+                    | Builtin::StartsWith | Builtin::EndsWith | Builtin::Format) => {}
+                _ => self.found = true,
+            },
+            ExprKind::Index { .. } => self.found = true,
+            ExprKind::Binary { op : BinOp::Div | BinOp::Mod, .. } if expr.ty == Type::Int => self.found = true,
+            _ => {}
+        }
+        visit::walk_expr(self, expr);
+    }
+}
+
+fn has_effects(e : &Expr) -> bool {
+    let mut effects = Effects::default();
+    effects.visit_expr(e);
+    effects.found
+}
+
+fn needs_order<'a>(values : impl IntoIterator<Item = &'a Expr>) -> bool {
+    let values : Vec<&Expr> = values.into_iter().filter(|value| !is_constant(value)).collect();
+    values.len() > 1 && values.iter().any(|value| has_effects(value))
+}
+
+fn temporary(span : Span, i : usize) -> String {
+    format!("fl_seq_{}_{}_{}", span.start, span.end, i)
+}
+
+// replaces the values that aren't constants with their temporaries, and
+// gives back the temporaries as (C type, name, code of the value it holds)
+fn hoist(span : Span, values : Vec<&mut Expr>) -> Vec<(String, String, String)> {
+    if !needs_order(values.iter().map(|value| &**value)) {
+        return vec![];
+    }
+    let mut hoisted = vec![];
+    for (i, value) in values.into_iter().enumerate() {
+        if is_constant(value) {
+            continue;
+        }
+        let name = temporary(span, i);
+        hoisted.push((c_type_name(&value.ty), name.clone(), expr(value)));
+        let kind = ExprKind::Var { name : name.clone(), c_name : name };
+        *value = Expr { kind, span : value.span, ty : value.ty.clone() };
+    }
+    hoisted
+}
+
+// the declarations of the temporaries of the expressions a statement
+// evaluates itself; nested statements and match arms declare their own
+#[derive(Default)]
+struct Temporaries {
+    out : String,
+    entered : bool,
+}
+
+impl Visitor for Temporaries {
+    fn visit_stmt(&mut self, stmt : &Stmt) {
+        if mem::replace(&mut self.entered, true) {
+            return;
+        }
+        match &stmt.kind {
+            // fl_array_set gets the array and the index, not the item
+            StmtKind::Set { target : Expr { kind : ExprKind::Index { array, index }, .. }, value } => {
+                self.visit_expr(array);
+                self.visit_expr(index);
+                self.visit_expr(value);
+            }
+            _ => visit::walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr : &Expr) {
+        if let ExprKind::Match { scrutinee, .. } = &expr.kind {
+            self.visit_expr(scrutinee);
+            return;
+        }
+        let operands = operands(expr);
+        if needs_order(operands.iter().copied()) {
+            for (i, operand) in operands.iter().enumerate() {
+                if !is_constant(operand) {
+                    self.out.push_str(&format!("{} {};\n", c_type_name(&operand.ty), temporary(expr.span, i)));
+                }
+            }
+        }
+        visit::walk_expr(self, expr);
+    }
+}
+
+// hoisted values for a statement, which can declare its temporaries in place
+fn declarations(hoisted : Vec<(String, String, String)>) -> String {
+    hoisted.into_iter().map(|(c_type, name, code)| format!("{} {} = {};\n", c_type, name, code)).collect()
+}
+
+fn temporaries(visit : impl FnOnce(&mut Temporaries)) -> String {
+    let mut temporaries = Temporaries::default();
+    visit(&mut temporaries);
+    temporaries.out
+}
+
+fn expr(e : &Expr) -> String {
+    sequenced(e, plain_expr)
+}
+
+// render(e), after the operands of e that need an order were stored in their
+// temporaries, as one comma expression
+fn sequenced(e : &Expr, render : fn(&Expr) -> String) -> String {
+    if !needs_order(operands(e)) {
+        return render(e);
+    }
+    let mut e = e.clone();
+    let span = e.span;
+    let assignments : Vec<String> = hoist(span, operands_mut(&mut e)).into_iter().map(|(_, name, code)| format!("{} = {}", name, code)).collect();
+    format!("({}, {})", assignments.join(", "), render(&e))
+}
+
+// the size, length and items of an array literal, as fl_array_new and fl_array_init take them
+fn array_args(e : &Expr) -> String {
+    let ExprKind::Array(elements) = &e.kind else { unreachable!("array_args of a {:?}", e.kind) };
+    let element = c_type_name(e.ty.element().unwrap());
+    if elements.is_empty() {
+        return format!("sizeof({}), 0, NULL", element);
+    }
+    let items = elements.iter().map(expr).collect::<Vec<_>>().join(", ");
+    format!("sizeof({}), {}, ({}[]){{{}}}", element, elements.len(), element, items)
+}
+
+// an array literal whose header is a compound literal, which lives until the
+// end of the enclosing block
+fn block_array(e : &Expr) -> String {
+    format!("fl_array_init(&(fl_array){{0}}, {})", array_args(e))
+}
+
+// a pointer to the element an Index reads
+fn element(e : &Expr) -> String {
+    let ExprKind::Index { array, index } = &e.kind else { unreachable!("element of a {:?}", e.kind) };
+    format!("(({}*) fl_array_at({}, {}, {}))", c_type_name(&e.ty), expr(array), expr(index), location(index))
+}
+
+// a pointer to the place a Set writes; a comma expression isn't an lvalue,
+// so the sequencing has to happen inside the `&`
+fn place(e : &Expr) -> String {
+    match &e.kind {
+        ExprKind::Index { .. } => sequenced(e, element),
+        ExprKind::Field { base, field, .. } => format!("(&{}->{})", place(base), user_name(&field.name)),
+        _ => format!("(&{})", expr(e)),
+    }
+}
+
+// expr, once its operands can be evaluated in any order
+fn plain_expr(e : &Expr) -> String {
+    match &e.kind {
+        ExprKind::Int(value) => value.to_string(),
+        // a float constant, so arithmetic on it stays in single precision like the interpreter's
+        ExprKind::Float(value) => format!("{:?}f", value), // always has a '.' or an exponent
+        ExprKind::Str(value) => c_string_literal(value),
+        ExprKind::Bool(value) => value.to_string(),
+        ExprKind::Var { c_name, .. } => c_name.clone(),
+        ExprKind::Array(_) => format!("fl_array_new({})", array_args(e)),
+        ExprKind::StructLit { name, fields } => {
+            let fields = fields.iter().map(|(field, value)| format!(".{} = {}", user_name(&field.name), expr(value))).collect::<Vec<_>>().join(", ");
+            format!("(({}){{{}}})", user_name(&name.name), fields)
+        }
+        ExprKind::Field { base, field, .. } => format!("{}.{}", expr(base), user_name(&field.name)),
+        ExprKind::Variant { variant, args, index, .. } => {
+            if args.is_empty() {
+                return format!("(({}){{.tag = {}}})", c_type_name(&e.ty), index);
+            }
+            let payload = args.iter().enumerate().map(|(i, arg)| format!("._{} = {}", i, expr(arg))).collect::<Vec<_>>().join(", ");
+            format!("(({}){{.tag = {}, .as.{} = {{{}}}}})", c_type_name(&e.ty), index, variant_member(&e.ty, &variant.name), payload)
+        }
+        ExprKind::Match { scrutinee, arms, id } => {
+            let mut args = vec![expr(scrutinee)];
+            args.extend(free_vars(arms).into_iter().map(|(name, _)| name));
+            format!("fl_match_{}({})", id, args.join(", "))
+        }
+        ExprKind::Index { .. } => format!("(*{})", element(e)),
+        ExprKind::Unary { op, operand, .. } => format!("({}{})", op.symbol(), expr(operand)),
+        ExprKind::Binary { op : BinOp::Add, left, right, .. } if left.ty == Type::String => {
+            format!("fl_concat({}, {})", expr(left), expr(right))
+        }
+        // C would compare the pointers
+        ExprKind::Binary { op, left, right, .. } if left.ty == Type::String => {
+            format!("(strcmp({}, {}) {} 0)", expr(left), expr(right), op.symbol())
+        }
+        ExprKind::Binary { op : op @ (BinOp::Div | BinOp::Mod), op_span, left, right } if e.ty == Type::Int => {
+            let function = if *op == BinOp::Div { "fl_div" } else { "fl_mod" };
+            format!("{}({}, {}, {}, {})", function, expr(left), expr(right), op_span.line, op_span.col)
+        }
+        ExprKind::Binary { op, left, right, .. } => format!("({} {} {})", expr(left), op.symbol(), expr(right)),
+        ExprKind::Call { name, args } if let Some(builtin) = Builtin::from_name(&name.name) => match builtin {
+            Builtin::Format if let ExprKind::Str(format) = &args[0].kind => format_call(format, &args[1..]),
+            Builtin::Len if args[0].ty == Type::String => format!("((int) strlen({}))", expr(&args[0])),
+            Builtin::Len => format!("({})->len", expr(&args[0])),
+            Builtin::Push => format!("fl_array_push({}, ({}[]){{{}}})", expr(&args[0]), c_type_name(&args[1].ty), expr(&args[1])),
+            Builtin::Pop => format!("(*({}*) fl_array_pop({}, {}))", c_type_name(&e.ty), expr(&args[0]), location(e)),
+            // these can fail, so they get the location to report
+            Builtin::Substr | Builtin::Split | Builtin::ReadLine | Builtin::ReadInt | Builtin::ReadFloat
+                | Builtin::TryReadInt | Builtin::TryReadFloat
+                | Builtin::ReadFile | Builtin::ReadLines | Builtin::WriteFile | Builtin::AppendFile => {
+                let mut args = args.iter().map(expr).collect::<Vec<_>>();
+                args.push(location(e));
+                format!("fl_{}({})", name.name, args.join(", "))
+            }
+            _ => {
+                let args = args.iter().map(expr).collect::<Vec<_>>().join(", ");
+                format!("fl_{}({})", name.name, args)
+            }
+        },
+        ExprKind::Call { name, args } => {
+            let args = args.iter().map(expr).collect::<Vec<_>>().join(", ");
+            format!("{}({})", user_name(&name.name), args)
+        }
+    }
+}
+
+// a format split into printf-style runs and the composite args between them,
+// which go through their fl_print_ function
+enum Piece<'a> {
+    Printf(String, Vec<String>),
+    Printer(&'a Expr),
+}
+
+fn pieces<'a>(format : &str, args : &'a [Expr], newline : bool) -> Vec<Piece<'a>> {
+    let mut pieces = vec![];
+    let mut c_format_str = String::new();
+    let mut c_args = vec![];
+
+    let mut args = args.iter();
+    for part in format::parse(format).expect("checked program: valid format") {
+        let spec = match part {
+            Part::Text(text) => {
+                c_format_str.push_str(&c_escape(&text.replace('%', "%%")));
+                continue;
+            }
+            Part::Placeholder(spec) => spec,
+        };
+        let arg = args.next().expect("checked program: an argument per placeholder");
+        if is_composite(&arg.ty) {
+            if !c_format_str.is_empty() {
+                pieces.push(Piece::Printf(mem::take(&mut c_format_str), mem::take(&mut c_args)));
+            }
+            pieces.push(Piece::Printer(arg));
+            continue;
+        }
+        let (conversion, c_arg) = conversion(&spec, arg);
+        c_format_str.push_str(&conversion);
+        c_args.push(c_arg);
+    }
+    if newline {
+        c_format_str.push_str("\\n");
+    }
+    if !c_format_str.is_empty() {
+        pieces.push(Piece::Printf(c_format_str, c_args));
+    }
+    pieces
+}
+
+fn print(format : &str, args : &[Expr], stream : Stream, newline : bool) -> String {
+    let mut out = String::new();
+    let (printf, fl_out) = match stream {
+        Stream::Stdout => ("printf(", "&fl_stdout"),
+        Stream::Stderr => {
+            // stdout first, so both come out in program order
+            out.push_str("fflush(stdout);\n");
+            ("fprintf(stderr, ", "&fl_stderr")
+        }
+    };
+    for piece in pieces(format, args, newline) {
+        match piece {
+            Piece::Printf(c_format_str, c_args) => out.push_str(&format!("{}{});\n", printf, printf_args(&c_format_str, &c_args))),
+            Piece::Printer(arg) => out.push_str(&format!("{}({}, {});\n", printer_name(&arg.ty), fl_out, expr(arg))),
+        }
+    }
+    if out.is_empty() {
+        // keeps `print("");` a statement
+        out.push_str("printf(\"\");\n");
+    }
+    out
+}
+
+// format(...) as nested calls that each add to the same buffer and return it
+fn format_call(format : &str, args : &[Expr]) -> String {
+    let mut out = "fl_buffer()".to_string();
+    for piece in pieces(format, args, false) {
+        out = match piece {
+            Piece::Printf(c_format_str, c_args) => format!("fl_write({}, {})", out, printf_args(&c_format_str, &c_args)),
+            Piece::Printer(arg) => format!("{}({}, {})", printer_name(&arg.ty), out, expr(arg)),
+        };
+    }
+    format!("fl_done({})", out)
+}
+
+// the printf conversion for one placeholder of a plain type, and its argument
+fn conversion(spec : &Spec, arg : &Expr) -> (String, String) {
+    let mut conversion = "%".to_string();
+    if let Some(width) = spec.width {
+        if spec.left_aligned(arg.ty.is_numeric()) {
+            conversion.push('-');
+        }
+        conversion.push_str(&width.to_string());
+    }
+    if let Some(precision) = spec.precision {
+        conversion.push_str(&format!(".{}", precision));
+    }
+    let value = expr(arg);
+    let (letter, c_arg) = match (spec.kind, &arg.ty) {
+        (Kind::Hex, _) => ('x', format!("(unsigned) ({})", value)),
+        // the buffer lives until the end of the printf statement
+        (Kind::Binary, _) => ('s', format!("fl_binary((char[33]){{0}}, {})", value)),
+        (Kind::Exp, _) => ('e', value),
+        (_, Type::Int) => ('d', value),
+        (_, Type::Float) => ('f', value),
+        (_, Type::Bool) => ('s', format!("({} ? \"true\" : \"false\")", value)),
+        _ => ('s', value),
+    };
+    conversion.push(letter);
+    (conversion, c_arg)
+}
+
+fn printf_args(c_format_str : &str, c_args : &[String]) -> String {
+    if c_args.is_empty() {
+        return format!("\"{}\"", c_format_str);
+    }
+    format!("\"{}\", {}", c_format_str, c_args.join(", "))
+}
+
+fn stmt(s : &Stmt, in_main : bool) -> String {
+    match &s.kind {
+        StmtKind::Print { format, args, stream, newline, .. } => {
+            let mut args = args.clone();
+            let hoisted = declarations(hoist(s.span, args.iter_mut().collect()));
+            format!("{}{}", hoisted, print(format, &args, *stream, *newline))
+        }
+        // every variable is a plain C local. FunLang copies arguments and return
+        // values and has no closures or `&`, so nothing can refer to a variable
+        // after its block ends. What an array variable points to can outlive it,
+        // so only an array the escape pass saw stay in its block skips the
+        // allocator, and its elements still don't, since push can grow them
+        StmtKind::Let { value, c_name, escapes : Some(false), .. } => format!("fl_array* {} = {};\n", c_name, sequenced(value, block_array)),
+        StmtKind::Let { value, c_name, .. } => format!("{} {} = {};\n", c_type_name(&value.ty), c_name, expr(value)),
+        StmtKind::Assign { value, c_name, .. } => format!("{} = {};\n", c_name, expr(value)),
+        StmtKind::Set { target, value } => match &target.kind {
+            ExprKind::Index { array, index } => {
+                let (mut array, mut index, mut value) = ((**array).clone(), (**index).clone(), value.clone());
+                let hoisted = declarations(hoist(s.span, vec![&mut array, &mut index, &mut value]));
+                let set = format!("fl_array_set({}, {}, ({}[]){{{}}}, {});\n", expr(&array), expr(&index), c_type_name(&value.ty), expr(&value), location(&index));
+                format!("{}{}", hoisted, set)
+            }
+            // the place first, then the value
+            _ if has_effects(target) => {
+                let pointer = temporary(s.span, 0);
+                format!("{}* {} = {};\n*{} = {};\n", c_type_name(&target.ty), pointer, place(target), pointer, expr(value))
+            }
+            _ => format!("{} = {};\n", expr(target), expr(value)),
+        },
+        StmtKind::If { branches, else_body } => {
+            let mut result = String::new();
+            for (i, (cond, body)) in branches.iter().enumerate() {
+                if i > 0 {
+                    result.push_str(" else ");
+                }
+                result.push_str(&format!("if ({}) {{\n{}}}", expr(cond), block(body, in_main)));
+            }
+            if let Some(else_body) = else_body {
+                result.push_str(&format!(" else {{\n{}}}", block(else_body, in_main)));
+            }
+            result.push('\n');
+            result
+        }
+        StmtKind::While { cond, body } => format!("while ({}) {{\n{}}}\n", expr(cond), block(body, in_main)),
+        StmtKind::For { c_var, start, end, step, body, .. } => {
+            let increment = match step {
+                Some(step) => format!("{} += {}", c_var, expr(step)),
+                None => format!("{}++", c_var),
+            };
+            format!(
+                "for (int {0} = {1}; {0} < {2}; {3}) {{\n{4}}}\n",
+                c_var, expr(start), expr(end), increment, block(body, in_main)
+            )
+        }
+        StmtKind::Return { value } => match value {
+            Some(value) => format!("return {};\n", expr(value)),
+            None if in_main => "return 0;\n".to_string(),
+            None => "return;\n".to_string(),
+        },
+        StmtKind::Match { scrutinee, arms } => {
+            let arms : Vec<(&Pattern, String)> = arms.iter().map(|(pattern, body)| (pattern, block(body, in_main))).collect();
+            format!("{{\n{} fl_match = {};\n{}}}\n", c_type_name(&scrutinee.ty), expr(scrutinee), switch(&scrutinee.ty, &arms))
+        }
+        StmtKind::Expr(e) => format!("{};\n", expr(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_c_string_contents() {
+        assert_eq!(c_escape("plain text 123"), "plain text 123");
+        assert_eq!(c_escape("say \"hi\" \\ bye"), "say \\\"hi\\\" \\\\ bye");
+        assert_eq!(c_escape("a\nb\tc\rd"), "a\\nb\\tc\\rd");
+        assert_eq!(c_escape("%d"), "%d");
+    }
+
+    #[test]
+    fn writes_other_bytes_in_octal() {
+        assert_eq!(c_escape("\u{1}\u{7f}"), "\\001\\177");
+        // always three digits, so a digit after one can't extend it
+        assert_eq!(c_escape("é1"), "\\303\\2511");
+        assert_eq!(c_escape("😀"), "\\360\\237\\230\\200");
+    }
+
+    #[test]
+    fn breaks_up_trigraphs() {
+        assert_eq!(c_escape("??="), "?\\?=");
+        assert_eq!(c_escape("what???"), "what?\\?\\?");
+        assert_eq!(c_string_literal("?"), "\"?\"");
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+
+use crate::source::{SourceFile,Span};
+
+// error codes, grouped by the phase that reports them
+pub const UNKNOWN_CHARACTER : &str = "E0001";
+pub const UNTERMINATED_STRING : &str = "E0002";
+pub const MALFORMED_NUMBER : &str = "E0003";
+pub const UNTERMINATED_COMMENT : &str = "E0004";
+pub const INVALID_ESCAPE : &str = "E0005";
+
+pub const UNEXPECTED_TOKEN : &str = "E0100";
+pub const STANDALONE_VALUE : &str = "E0101";
+pub const MISPLACED_DECLARATION : &str = "E0102";
+pub const DUPLICATE_DEFINITION : &str = "E0103";
+pub const EMPTY_STRUCT : &str = "E0104";
+pub const EMPTY_ENUM : &str = "E0105";
+
+pub const UNDEFINED_VARIABLE : &str = "E0200";
+pub const UNDEFINED_FUNCTION : &str = "E0201";
+pub const UNDEFINED_TYPE : &str = "E0202";
+pub const UNKNOWN_FIELD : &str = "E0203";
+pub const UNKNOWN_VARIANT : &str = "E0204";
+pub const MISSING_MAIN : &str = "E0205";
+
+pub const TYPE_MISMATCH : &str = "E0300";
+pub const ARGUMENT_COUNT : &str = "E0301";
+pub const INVALID_OPERATOR : &str = "E0302";
+pub const PRINT_FORMAT : &str = "E0303";
+pub const MISSING_FIELD : &str = "E0304";
+pub const NOT_ASSIGNABLE : &str = "E0305";
+pub const NON_EXHAUSTIVE : &str = "E0306";
+pub const UNREACHABLE_ARM : &str = "E0307";
+pub const MISSING_RETURN : &str = "E0308";
+
+pub const IO_ERROR : &str = "E0400";
+pub const CODEGEN_ERROR : &str = "E0401";
+pub const MISSING_LIBRARY : &str = "E0402";
+pub const NO_C_COMPILER : &str = "E0403";
+
+pub const RUNTIME_ERROR : &str = "E0500";
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity : Severity,
+    pub code : &'static str,
+    pub message : String,
+    pub span : Option<Span>,
+    pub notes : Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code : &'static str, message : String) -> Self {
+        Self {
+            severity : Severity::Error,
+            code,
+            message,
+            span : None,
+            notes : vec![],
+        }
+    }
+
+    pub fn with_span(mut self, span : Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_note(mut self, note : String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    // `source` is None when the failure happened before the file could be read
+    pub fn render(&self, source : Option<&SourceFile>) -> String {
+        let mut out = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+        if let (Some(span), Some(source)) = (self.span, source) {
+            out.push_str(&source.snippet(span));
+        }
+        for note in &self.notes {
+            out.push_str(&format!("  = note: {}\n", note));
+        }
+        out
+    }
+}
+
+// lets single-error phases use `?` in functions that report several errors
+impl From<Diagnostic> for Vec<Diagnostic> {
+    fn from(d : Diagnostic) -> Self {
+        vec![d]
+    }
+}
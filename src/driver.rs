@@ -0,0 +1,197 @@
+use std::fs;
+use std::path::{Path,PathBuf};
+use std::process;
+
+use tempfile::TempDir;
+
+use crate::cc::{self,CcOptions};
+use crate::checker;
+use crate::codegen;
+use crate::diagnostic::{self,Diagnostic};
+use crate::escape;
+use crate::interp;
+use crate::lexer::{Scanner,Token,TokenValue};
+use crate::nodes::Program;
+use crate::paser::Parser;
+use crate::runtime::{self,Memory};
+use crate::source::SourceFile;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Build,
+    Run,
+    Check,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    C,
+    Exe,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub command : Command,
+    pub input : PathBuf,
+    pub output : Option<PathBuf>,
+    pub emit : Emit,
+    pub interpret : bool, // run without going through C
+    pub cc : CcOptions,
+}
+
+pub fn read_source(path : &Path) -> Result<SourceFile, Diagnostic> {
+    let text = fs::read_to_string(path).map_err(|e| {
+        Diagnostic::error(diagnostic::IO_ERROR, format!("Error reading file '{}': {}", path.display(), e))
+    })?;
+    Ok(SourceFile::new(&path.display().to_string(), text))
+}
+
+pub fn tokenize(source : &SourceFile) -> Result<Vec<Token>, Diagnostic> {
+    let mut scanner = Scanner::new(source.text.clone());
+    scanner.tokenize()?;
+    Ok(scanner.toks)
+}
+
+pub fn parse(source : &SourceFile) -> Result<Program, Diagnostic> {
+    let mut parser = Parser::new(tokenize(source)?);
+    parser.parse()
+}
+
+// parses and type checks, reporting every error the checker finds
+pub fn check(source : &SourceFile) -> Result<Program, Vec<Diagnostic>> {
+    let mut program = parse(source)?;
+    checker::check_program(&mut program)?;
+    Ok(escape::analyze(program))
+}
+
+pub fn compile(source : &SourceFile, memory : Memory) -> Result<String, Vec<Diagnostic>> {
+    let program = check(source)?;
+
+    let code = codegen::generate(&program);
+    let mut out = runtime::prelude(memory, &source.name, &code);
+    out.push_str(&code);
+    Ok(out)
+}
+
+fn format_tokens(toks : &[Token]) -> String {
+    let mut out = String::new();
+    for tok in toks {
+        for trivia in &tok.trivia {
+            out.push_str(&format!("{}:{}\t{:?}\t{:?}\n", trivia.span.line, trivia.span.col, trivia.kind, trivia.text));
+        }
+        out.push_str(&format!("{}:{}\t{:?}", tok.span.line, tok.span.col, tok.tok_type));
+        if tok.tok_value != TokenValue::NONE {
+            out.push_str(&format!("\t{:?}", tok.tok_value));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn format_ast(program : &Program) -> String {
+    let mut out = serde_json::to_string_pretty(program).expect("the AST always serializes");
+    out.push('\n');
+    out
+}
+
+fn write_output(output : Option<&Path>, text : &str) -> Result<(), Diagnostic> {
+    match output {
+        Some(path) => fs::write(path, text).map_err(|e| {
+            Diagnostic::error(diagnostic::IO_ERROR, format!("Error writing '{}': {}", path.display(), e))
+        }),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+// a new directory with a random name that only we can use, removed when dropped
+fn temp_dir() -> Result<TempDir, Diagnostic> {
+    tempfile::Builder::new().prefix("funlang-").tempdir().map_err(|e| {
+        Diagnostic::error(diagnostic::IO_ERROR, format!("Could not create a temporary directory: {}", e))
+    })
+}
+
+fn build_executable(source : &SourceFile, exe : &Path, options : &CcOptions) -> Result<(), Vec<Diagnostic>> {
+    let c_code = compile(source, options.memory)?;
+    let compiler = cc::find_compiler()?;
+
+    let dir = temp_dir()?;
+    let c_path = dir.path().join("main.c");
+    write_output(Some(&c_path), &c_code)?;
+    let result = compiler.compile(&c_path, exe, options);
+    // the report of a codegen bug points at the C it kept there
+    if result.as_ref().is_err_and(|d| d.code == diagnostic::CODEGEN_ERROR) {
+        let _ = dir.keep();
+    }
+    Ok(result?)
+}
+
+fn default_exe_path(input : &Path) -> PathBuf {
+    let stem = input.file_stem().map(|s| s.to_os_string()).unwrap_or_else(|| "a.out".into());
+    PathBuf::from(stem)
+}
+
+// returns the process exit code
+fn run_command(options : &Options, source : &SourceFile) -> Result<i32, Vec<Diagnostic>> {
+    match options.command {
+        Command::Check => {
+            check(source)?;
+            Ok(0)
+        }
+        Command::Build => {
+            let output = options.output.as_deref();
+            match options.emit {
+                Emit::Tokens => write_output(output, &format_tokens(&tokenize(source)?))?,
+                Emit::Ast => write_output(output, &format_ast(&check(source)?))?,
+                Emit::C => write_output(output, &compile(source, options.cc.memory)?)?,
+                Emit::Exe => {
+                    let exe = options.output.clone().unwrap_or_else(|| default_exe_path(&options.input));
+                    build_executable(source, &exe, &options.cc)?;
+                }
+            }
+            Ok(0)
+        }
+        Command::Run if options.interpret => {
+            let program = check(source)?;
+            Ok(interp::run(program)?)
+        }
+        Command::Run => {
+            let dir = temp_dir()?;
+            let exe = dir.path().join("main");
+            build_executable(source, &exe, &options.cc)?;
+            let status = process::Command::new(&exe).status();
+            match status {
+                Ok(status) => match status.code() {
+                    Some(code) => Ok(code),
+                    // a signal, like SIGSEGV when deep recursion overflows the stack
+                    None => Err(vec![Diagnostic::error(diagnostic::RUNTIME_ERROR, format!("The program was terminated ({})", status))]),
+                },
+                Err(e) => Err(vec![Diagnostic::error(diagnostic::IO_ERROR, format!("Could not run '{}': {}", exe.display(), e))]),
+            }
+        }
+    }
+}
+
+pub fn execute(options : &Options) -> i32 {
+    let source = match read_source(&options.input) {
+        Ok(source) => source,
+        Err(d) => {
+            eprint!("{}", d.render(None));
+            return 1;
+        }
+    };
+
+    match run_command(options, &source) {
+        Ok(code) => code,
+        Err(diagnostics) => {
+            for d in &diagnostics {
+                eprint!("{}", d.render(Some(&source)));
+            }
+            1
+        }
+    }
+}
@@ -0,0 +1,165 @@
+// Escape analysis: decides which arrays created by a let can live in the let's
+// block instead of being allocated. Numbers, bools, structs and enums are
+// copied, so variables themselves never escape, but an array variable holds a
+// reference, and the array escapes when that reference can still be used after
+// the block ends: it's returned, passed to a function, stored somewhere,
+// assigned to another variable, and so on. Indexing it, len, push, pop,
+// printing it and copying it into a new variable with let don't make it escape;
+// the copies are followed, since they can only live in the same block or an
+// inner one.
+use std::collections::{HashMap,HashSet};
+
+use crate::nodes::*;
+use crate::visit::{self,Fold,Visitor};
+
+// runs after the checker, since it goes by the c_names, which are unique
+// within a function
+pub fn analyze(program : Program) -> Program {
+    let mut escapes = Escapes { function : String::new(), arrays : HashMap::new(), escaping : HashSet::new() };
+    escapes.visit_program(&program);
+    Storage { function : String::new(), escaping : escapes.escaping }.fold_program(program)
+}
+
+// finds the escaping arrays, as (function, c_name of the let that creates it)
+struct Escapes {
+    function : String,
+    arrays : HashMap<String,String>, // a variable holding an array a let created -> that let
+    escaping : HashSet<(String,String)>,
+}
+
+impl Escapes {
+    fn array(&self, expr : &Expr) -> Option<&String> {
+        match &expr.kind {
+            ExprKind::Var { c_name, .. } => self.arrays.get(c_name),
+            _ => None,
+        }
+    }
+
+    // an expression that is used up where it appears, so an array variable
+    // there doesn't escape
+    fn visit_used(&mut self, expr : &Expr) {
+        if self.array(expr).is_none() {
+            self.visit_expr(expr);
+        }
+    }
+}
+
+impl Visitor for Escapes {
+    fn visit_function(&mut self, function : &Function) {
+        self.function = function.name.name.clone();
+        self.arrays.clear();
+        visit::walk_function(self, function);
+    }
+
+    fn visit_stmt(&mut self, stmt : &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { value, c_name, .. } if let ExprKind::Array(_) = value.kind => {
+                visit::walk_expr(self, value);
+                self.arrays.insert(c_name.clone(), c_name.clone());
+            }
+            StmtKind::Let { value, c_name, .. } if let Some(array) = self.array(value) => {
+                self.arrays.insert(c_name.clone(), array.clone());
+            }
+            StmtKind::Print { args, .. } => {
+                for arg in args {
+                    self.visit_used(arg);
+                }
+            }
+            _ => visit::walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr : &Expr) {
+        match &expr.kind {
+            ExprKind::Var { c_name, .. } => {
+                if let Some(array) = self.arrays.get(c_name) {
+                    self.escaping.insert((self.function.clone(), array.clone()));
+                }
+            }
+            ExprKind::Index { array, index } => {
+                self.visit_used(array);
+                self.visit_expr(index);
+            }
+            ExprKind::Call { name, args } => match Builtin::from_name(&name.name) {
+                Some(Builtin::Len | Builtin::Pop) => self.visit_used(&args[0]),
+                // the pushed value is stored in the array
+                Some(Builtin::Push) => {
+                    self.visit_used(&args[0]);
+                    self.visit_expr(&args[1]);
+                }
+                Some(Builtin::Format) => {
+                    for arg in args {
+                        self.visit_used(arg);
+                    }
+                }
+                _ => visit::walk_expr(self, expr),
+            },
+            _ => visit::walk_expr(self, expr),
+        }
+    }
+}
+
+// writes the result into the lets, where codegen reads it
+struct Storage {
+    function : String,
+    escaping : HashSet<(String,String)>,
+}
+
+impl Fold for Storage {
+    fn fold_function(&mut self, function : Function) -> Function {
+        self.function = function.name.name.clone();
+        visit::fold_function(self, function)
+    }
+
+    fn fold_stmt(&mut self, stmt : Stmt) -> Stmt {
+        let mut stmt = visit::fold_stmt(self, stmt);
+        if let StmtKind::Let { value : Expr { kind : ExprKind::Array(_), .. }, c_name, escapes, .. } = &mut stmt.kind {
+            *escapes = Some(self.escaping.contains(&(self.function.clone(), c_name.clone())));
+        }
+        stmt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver;
+    use crate::source::SourceFile;
+
+    // the lets of main that create an array, and whether the array escapes
+    fn escapes(body : &str) -> Vec<(String, bool)> {
+        let source = SourceFile::new("test.fun", format!("fun keep(xs : [int]) {{\n}}\nfun main() {{\n{}\n}}\n", body));
+        let program = driver::check(&source).expect("test programs check");
+        let mut lets = vec![];
+        let mut pending = program.main.unwrap().body;
+        while let Some(stmt) = pending.pop() {
+            match stmt.kind {
+                StmtKind::Let { c_name, escapes : Some(escapes), .. } => lets.push((c_name, escapes)),
+                StmtKind::If { branches, .. } => pending.extend(branches.into_iter().flat_map(|(_, body)| body)),
+                StmtKind::For { body, .. } | StmtKind::While { body, .. } => pending.extend(body),
+                _ => {}
+            }
+        }
+        lets.sort();
+        lets
+    }
+
+    fn array(c_name : &str, escapes : bool) -> (String, bool) {
+        (c_name.to_string(), escapes)
+    }
+
+    #[test]
+    fn arrays_used_in_place_stay() {
+        assert_eq!(escapes("let a = [1, 2];\npush(a, 3);\na[0] = pop(a) + len(a);\nprintln(\"{} {}\", a, a[1]);"), vec![array("u_a", false)]);
+        assert_eq!(escapes("for 0:3 i {\nlet row = [i];\nlet same = row;\npush(same, i);\n}"), vec![array("u_row", false)]);
+        assert_eq!(escapes("let a : [int] = [];\nlet text = format(\"{}\", a);"), vec![array("u_a", false)]);
+    }
+
+    #[test]
+    fn arrays_that_outlive_their_block_escape() {
+        assert_eq!(escapes("let a = [1];\nkeep(a);"), vec![array("u_a", true)]);
+        assert_eq!(escapes("let a = [1];\nlet grid = [a];"), vec![array("u_a", true), array("u_grid", false)]);
+        assert_eq!(escapes("let grid : [[int]] = [];\nlet a = [1];\npush(grid, a);"), vec![array("u_a", true), array("u_grid", false)]);
+        assert_eq!(escapes("let outer = [0];\nif true {\nlet a = [1];\nlet b = a;\nouter = b;\n}"), vec![array("u_a", true), array("u_outer", false)]);
+    }
+}
@@ -0,0 +1,221 @@
+// Print formats: text with `{}` placeholders, which can carry a spec like
+// `{:<8}`, `{:.2}` or `{:x}`, and `{{`/`}}` for literal braces. The checker
+// validates them; codegen and the interpreter parse the checked format again.
+use crate::nodes::Type;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Part {
+    Text(String),
+    Placeholder(Spec),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Spec {
+    pub align : Option<Align>, // None is right for numbers, left for the rest, like Rust
+    pub width : Option<usize>, // counts bytes, like printf
+    pub precision : Option<usize>,
+    pub kind : Kind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Kind {
+    #[default]
+    Display,
+    Hex,
+    Binary,
+    Exp,
+}
+
+impl Spec {
+    // an error message if `ty` can't be printed with this spec
+    pub fn check(&self, ty : &Type) -> Result<(), String> {
+        let plain = matches!(ty, Type::Int | Type::Float | Type::String | Type::Bool);
+        if !plain && *self != Spec::default() {
+            return Err(format!("A value of type '{}' can only be printed with a plain {{}}", ty));
+        }
+        match self.kind {
+            Kind::Hex | Kind::Binary if *ty != Type::Int => {
+                return Err(format!("'{}' needs an int, but got '{}'", self.kind_name(), ty));
+            }
+            Kind::Exp if *ty != Type::Float => return Err(format!("'e' needs a float, but got '{}'", ty)),
+            _ => {}
+        }
+        if self.precision.is_some() && *ty != Type::Float {
+            return Err(format!("A precision needs a float, but got '{}'", ty));
+        }
+        Ok(())
+    }
+
+    pub fn left_aligned(&self, numeric : bool) -> bool {
+        self.align.map_or(!numeric, |align| align == Align::Left)
+    }
+
+    fn kind_name(&self) -> &'static str {
+        match self.kind {
+            Kind::Display => "",
+            Kind::Hex => "x",
+            Kind::Binary => "b",
+            Kind::Exp => "e",
+        }
+    }
+}
+
+pub fn placeholder_count(parts : &[Part]) -> usize {
+    parts.iter().filter(|part| matches!(part, Part::Placeholder(_))).count()
+}
+
+// text and placeholders in order; neighbouring text is merged
+pub fn parse(format : &str) -> Result<Vec<Part>, String> {
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '}' => return Err("Unmatched '}' in the format string, write '}}' to print one".to_string()),
+            '{' => {
+                let mut inside = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(ch) => inside.push(ch),
+                        None => return Err("Unmatched '{' in the format string, write '{{' to print one".to_string()),
+                    }
+                }
+                if !text.is_empty() {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                }
+                parts.push(Part::Placeholder(parse_spec(&inside)?));
+            }
+            ch => text.push(ch),
+        }
+    }
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+    Ok(parts)
+}
+
+// what goes between the braces: nothing, or `:[<|>][width][.precision][x|b|e]`
+fn parse_spec(inside : &str) -> Result<Spec, String> {
+    let invalid = || format!("Invalid placeholder '{{{}}}'", inside);
+    if inside.is_empty() {
+        return Ok(Spec::default());
+    }
+    let mut rest = inside.strip_prefix(':').ok_or_else(invalid)?;
+    let mut spec = Spec::default();
+    if let Some(after) = rest.strip_prefix('<') {
+        spec.align = Some(Align::Left);
+        rest = after;
+    } else if let Some(after) = rest.strip_prefix('>') {
+        spec.align = Some(Align::Right);
+        rest = after;
+    }
+    let digits = |s : &str| s.find(|c : char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let n = digits(rest);
+    if n > 0 {
+        // a leading 0 would be zero padding in Rust and C, which isn't supported
+        if rest.starts_with('0') && n > 1 {
+            return Err(invalid());
+        }
+        spec.width = Some(rest[..n].parse::<i32>().map_err(|_| invalid())? as usize);
+        rest = &rest[n..];
+    }
+    if let Some(after) = rest.strip_prefix('.') {
+        let n = digits(after);
+        if n == 0 {
+            return Err(invalid());
+        }
+        spec.precision = Some(after[..n].parse::<i32>().map_err(|_| invalid())? as usize);
+        rest = &after[n..];
+    }
+    spec.kind = match rest {
+        "" => Kind::Display,
+        "x" => Kind::Hex,
+        "b" => Kind::Binary,
+        "e" => Kind::Exp,
+        _ => return Err(invalid()),
+    };
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(align : Option<Align>, width : Option<usize>, precision : Option<usize>, kind : Kind) -> Spec {
+        Spec { align, width, precision, kind }
+    }
+
+    #[test]
+    fn parses_text_placeholders_and_brace_escapes() {
+        let parts = parse("a {{b}} {} c{}").unwrap();
+        assert_eq!(parts, vec![
+            Part::Text("a {b} ".to_string()),
+            Part::Placeholder(Spec::default()),
+            Part::Text(" c".to_string()),
+            Part::Placeholder(Spec::default()),
+        ]);
+        assert_eq!(placeholder_count(&parts), 2);
+        assert_eq!(parse("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn parses_specs() {
+        let only = |format : &str| match parse(format).unwrap().as_slice() {
+            [Part::Placeholder(spec)] => *spec,
+            parts => panic!("expected one placeholder, got {:?}", parts),
+        };
+        assert_eq!(only("{:8}"), spec(None, Some(8), None, Kind::Display));
+        assert_eq!(only("{:<8}"), spec(Some(Align::Left), Some(8), None, Kind::Display));
+        assert_eq!(only("{:>10.3}"), spec(Some(Align::Right), Some(10), Some(3), Kind::Display));
+        assert_eq!(only("{:.2}"), spec(None, None, Some(2), Kind::Display));
+        assert_eq!(only("{:x}"), spec(None, None, None, Kind::Hex));
+        assert_eq!(only("{:4b}"), spec(None, Some(4), None, Kind::Binary));
+        assert_eq!(only("{:.0e}"), spec(None, None, Some(0), Kind::Exp));
+        assert_eq!(only("{:0}"), spec(None, Some(0), None, Kind::Display));
+    }
+
+    #[test]
+    fn rejects_bad_formats() {
+        for format in ["{", "}", "a } b", "{:", "{x}", "{:08}", "{:.}", "{:q}", "{:8.2.1}", "{:^8}", "{:99999999999}"] {
+            assert!(parse(format).is_err(), "{:?} should be rejected", format);
+        }
+    }
+
+    #[test]
+    fn checks_specs_against_types() {
+        let array = Type::Array(Box::new(Type::Int));
+        assert!(Spec::default().check(&array).is_ok());
+        assert!(spec(None, Some(4), None, Kind::Display).check(&array).is_err());
+        assert!(spec(None, None, None, Kind::Hex).check(&Type::Int).is_ok());
+        assert!(spec(None, None, None, Kind::Hex).check(&Type::Float).is_err());
+        assert!(spec(None, None, None, Kind::Binary).check(&Type::Bool).is_err());
+        assert!(spec(None, None, None, Kind::Exp).check(&Type::Float).is_ok());
+        assert!(spec(None, None, None, Kind::Exp).check(&Type::Int).is_err());
+        assert!(spec(None, None, Some(2), Kind::Display).check(&Type::Float).is_ok());
+        assert!(spec(None, None, Some(2), Kind::Display).check(&Type::String).is_err());
+        assert!(spec(Some(Align::Left), Some(6), None, Kind::Display).check(&Type::String).is_ok());
+    }
+
+    #[test]
+    fn aligns_numbers_right_and_the_rest_left() {
+        assert!(!Spec::default().left_aligned(true));
+        assert!(Spec::default().left_aligned(false));
+        assert!(spec(Some(Align::Left), None, None, Kind::Display).left_aligned(true));
+        assert!(!spec(Some(Align::Right), None, None, Kind::Display).left_aligned(false));
+    }
+}
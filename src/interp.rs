@@ -0,0 +1,797 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self,File,OpenOptions};
+use std::io::{self,BufRead,BufWriter,Stdout,Write};
+use std::rc::Rc;
+use std::thread;
+
+use crate::diagnostic::{self,Diagnostic};
+use crate::format::{self,Kind,Part,Spec};
+use crate::nodes::*;
+use crate::source::Span;
+
+// deep FunLang recursion turns into deep Rust recursion here, so the interpreter
+// runs on its own thread with a big stack and gives up past MAX_DEPTH calls
+const STACK_SIZE : usize = 256 * 1024 * 1024;
+const MAX_DEPTH : usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Float(f64), // always an f32 value, like a C float, which printf gets as a double
+    Str(String),
+    Bool(bool),
+    Array(Rc<RefCell<Vec<Value>>>), // shared, like the fl_array* of the C backend
+    Struct(Rc<StructDef>, Vec<Value>), // copied, like a C struct
+    Enum(Rc<EnumDef>, usize, Vec<Value>), // the variant and its payload
+    Void,
+}
+
+impl Value {
+    fn as_int(&self) -> i32 {
+        match self {
+            Value::Int(i) => *i,
+            _ => unreachable!("checked program: expected int, got {:?}", self),
+        }
+    }
+
+    fn as_float(&self) -> f64 {
+        match self {
+            Value::Int(i) => *i as f64,
+            Value::Float(f) => *f,
+            _ => unreachable!("checked program: expected number, got {:?}", self),
+        }
+    }
+
+    fn as_bool(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            _ => unreachable!("checked program: expected bool, got {:?}", self),
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Value::Str(s) => s,
+            _ => unreachable!("checked program: expected string, got {:?}", self),
+        }
+    }
+
+    fn as_array(&self) -> &Rc<RefCell<Vec<Value>>> {
+        match self {
+            Value::Array(items) => items,
+            _ => unreachable!("checked program: expected array, got {:?}", self),
+        }
+    }
+
+    // strings are quoted inside arrays and structs
+    fn item_string(&self) -> String {
+        match self {
+            Value::Str(s) => format!("\"{}\"", s),
+            value => value.to_c_string(),
+        }
+    }
+
+    // what printf would print for the matching %d / %f / %s
+    pub fn to_c_string(&self) -> String {
+        match self {
+            Value::Int(i) => i.to_string(),
+            Value::Float(f) if f.is_nan() => if f.is_sign_negative() { "-nan".to_string() } else { "nan".to_string() },
+            Value::Float(f) if f.is_infinite() => if *f < 0.0 { "-inf".to_string() } else { "inf".to_string() },
+            Value::Float(f) => format!("{:.6}", f),
+            Value::Str(s) => s.clone(),
+            Value::Bool(b) => b.to_string(),
+            Value::Array(items) => {
+                let items = items.borrow().iter().map(Value::item_string).collect::<Vec<_>>();
+                format!("[{}]", items.join(", "))
+            }
+            Value::Struct(def, fields) => {
+                let fields = def.fields.iter().zip(fields).map(|(field, value)| format!("{}: {}", field.name.name, value.item_string())).collect::<Vec<_>>();
+                format!("{} {{ {} }}", def.name.name, fields.join(", "))
+            }
+            Value::Enum(def, variant, payload) if payload.is_empty() => def.variants[*variant].name.name.clone(),
+            Value::Enum(def, variant, payload) => {
+                let payload = payload.iter().map(Value::item_string).collect::<Vec<_>>();
+                format!("{}({})", def.variants[*variant].name.name, payload.join(", "))
+            }
+            Value::Void => String::new(),
+        }
+    }
+}
+
+pub enum Flow {
+    Next,
+    Return(Value),
+}
+
+// the variables of one function call (or of the REPL), innermost block last
+#[derive(Clone)]
+pub struct Env {
+    scopes : Vec<HashMap<String,Value>>,
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Self { scopes : vec![HashMap::new()] }
+    }
+
+    fn declare(&mut self, name : &str, value : Value) {
+        self.scopes.last_mut().unwrap().insert(name.to_string(), value);
+    }
+
+    fn get(&self, name : &str) -> &Value {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).expect("checked program: variable in scope")
+    }
+
+    fn get_mut(&mut self, name : &str) -> &mut Value {
+        self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name)).expect("checked program: variable in scope")
+    }
+
+    fn set(&mut self, name : &str, value : Value) {
+        *self.get_mut(name) = value;
+    }
+}
+
+fn runtime_error(message : &str, span : Span) -> Diagnostic {
+    Diagnostic::error(diagnostic::RUNTIME_ERROR, message.to_string()).with_span(span)
+}
+
+// where `a[i].x = value` and friends write: a variable or an array element,
+// followed by the indices of the fields to go through
+enum Place {
+    Var(String),
+    Item(Rc<RefCell<Vec<Value>>>, usize),
+}
+
+pub struct Interpreter {
+    structs : HashMap<String,Rc<StructDef>>,
+    enums : HashMap<String,Rc<EnumDef>>,
+    functions : HashMap<String,Rc<Function>>,
+    out : BufWriter<Stdout>,
+    depth : usize,
+}
+
+// runs `f` on a thread with a stack big enough for MAX_DEPTH calls
+pub fn with_big_stack<T : Send>(f : impl FnOnce() -> Result<T, Diagnostic> + Send) -> Result<T, Diagnostic> {
+    thread::scope(|scope| {
+        let worker = thread::Builder::new().stack_size(STACK_SIZE).spawn_scoped(scope, f);
+        match worker {
+            Ok(handle) => handle.join().unwrap_or_else(|_| Err(Diagnostic::error(diagnostic::RUNTIME_ERROR, "The interpreter crashed".to_string()))),
+            Err(e) => Err(Diagnostic::error(diagnostic::RUNTIME_ERROR, format!("Could not start the interpreter: {}", e))),
+        }
+    })
+}
+
+// runs a checked program and returns its exit code
+pub fn run(program : Program) -> Result<i32, Diagnostic> {
+    with_big_stack(move || {
+        let main = match &program.main {
+            Some(main) => main.clone(),
+            None => return Err(Diagnostic::error(diagnostic::UNDEFINED_FUNCTION, "The program has no main function".to_string())),
+        };
+        let mut interp = Interpreter::new();
+        for def in program.structs {
+            interp.define_struct(def);
+        }
+        for def in program.enums {
+            interp.define_enum(def);
+        }
+        for function in program.functions {
+            interp.define(function);
+        }
+        let result = interp.call(&main, vec![], main.span);
+        interp.flush();
+        result.map(|_| 0)
+    })
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self { structs : HashMap::new(), enums : HashMap::new(), functions : HashMap::new(), out : BufWriter::new(io::stdout()), depth : 0 }
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+
+    pub fn define_struct(&mut self, def : StructDef) {
+        self.structs.insert(def.name.name.clone(), Rc::new(def));
+    }
+
+    pub fn define_enum(&mut self, def : EnumDef) {
+        self.enums.insert(def.name.name.clone(), Rc::new(def));
+    }
+
+    pub fn define(&mut self, function : Function) {
+        self.functions.insert(function.name.name.clone(), Rc::new(function));
+    }
+
+    // runs one statement in the innermost scope of `env`
+    pub fn exec(&mut self, stmt : &Stmt, env : &mut Env) -> Result<Flow, Diagnostic> {
+        self.stmt(stmt, env)
+    }
+
+    pub fn eval(&mut self, e : &Expr, env : &mut Env) -> Result<Value, Diagnostic> {
+        self.expr(e, env)
+    }
+
+    // the input builtins, which read the same way as the C runtime: lines
+    // without their line break, numbers as whitespace-separated words
+    fn read(&mut self, builtin : Builtin, span : Span) -> Result<Value, Diagnostic> {
+        self.flush();
+        let mut input = io::stdin().lock();
+        let (name, ty) = match builtin {
+            Builtin::ReadLine | Builtin::TryReadLine => ("read_line", Type::String),
+            Builtin::ReadInt | Builtin::TryReadInt => ("read_int", Type::Int),
+            _ => ("read_float", Type::Float),
+        };
+        let value = match &ty {
+            Type::String => next_line(&mut input).map(Value::Str),
+            _ => match next_word(&mut input) {
+                Some(word) => Some(parse_number(name, &word, &ty, span)?),
+                None => None,
+            },
+        };
+        match builtin {
+            Builtin::TryReadLine | Builtin::TryReadInt | Builtin::TryReadFloat => {
+                let def = Rc::new(EnumDef::optional(&ty));
+                Ok(match value {
+                    Some(value) => Value::Enum(def, 0, vec![value]),
+                    None => Value::Enum(def, 1, vec![]),
+                })
+            }
+            _ => value.ok_or_else(|| runtime_error(&format!("{}() reached the end of the input", name), span)),
+        }
+    }
+
+    fn call(&mut self, function : &Function, args : Vec<Value>, span : Span) -> Result<Value, Diagnostic> {
+        if self.depth >= MAX_DEPTH {
+            return Err(runtime_error("Stack overflow: too many nested calls", span)
+                .with_note(format!("the interpreter allows {} nested calls", MAX_DEPTH)));
+        }
+        self.depth += 1;
+
+        let mut env = Env::new();
+        for (param, value) in function.params.iter().zip(args) {
+            env.declare(&param.name.name, value);
+        }
+        let flow = self.block(&function.body, &mut env);
+        self.depth -= 1;
+
+        Ok(match flow? {
+            Flow::Return(value) => value,
+            Flow::Next => Value::Void,
+        })
+    }
+
+    fn block(&mut self, stmts : &[Stmt], env : &mut Env) -> Result<Flow, Diagnostic> {
+        env.scopes.push(HashMap::new());
+        let mut flow = Ok(Flow::Next);
+        for stmt in stmts {
+            flow = self.stmt(stmt, env);
+            if !matches!(flow, Ok(Flow::Next)) {
+                break;
+            }
+        }
+        env.scopes.pop();
+        flow
+    }
+
+    fn stmt(&mut self, stmt : &Stmt, env : &mut Env) -> Result<Flow, Diagnostic> {
+        match &stmt.kind {
+            StmtKind::Print { format, args, stream, newline, .. } => {
+                let mut values = vec![];
+                for arg in args {
+                    values.push(self.expr(arg, env)?);
+                }
+                let mut text = format_print(format, &values);
+                if *newline {
+                    text.push('\n');
+                }
+                match stream {
+                    Stream::Stdout => {
+                        let _ = self.out.write_all(text.as_bytes());
+                    }
+                    // stdout first, so both come out in program order
+                    Stream::Stderr => {
+                        self.flush();
+                        let _ = io::stderr().write_all(text.as_bytes());
+                    }
+                }
+            }
+            StmtKind::Let { name, value, .. } => {
+                let value = self.expr(value, env)?;
+                env.declare(&name.name, value);
+            }
+            StmtKind::Assign { name, value, .. } => {
+                let value = self.expr(value, env)?;
+                env.set(&name.name, value);
+            }
+            // like fl_array_set in C: the value first, then the bounds check
+            StmtKind::Set { target : Expr { kind : ExprKind::Index { array, index }, .. }, value } => {
+                let items = self.expr(array, env)?;
+                let i = self.expr(index, env)?.as_int();
+                let value = self.expr(value, env)?;
+                let mut items = items.as_array().borrow_mut();
+                let i = in_bounds(i, items.len(), index.span)?;
+                items[i] = value;
+            }
+            StmtKind::Set { target, value } => {
+                let mut path = vec![];
+                let place = self.place(target, &mut path, env)?;
+                let value = self.expr(value, env)?;
+                match place {
+                    Place::Var(name) => *field_at(env.get_mut(&name), &path) = value,
+                    Place::Item(items, i) => *field_at(&mut items.borrow_mut()[i], &path) = value,
+                }
+            }
+            StmtKind::If { branches, else_body } => {
+                for (cond, body) in branches {
+                    if self.expr(cond, env)?.as_bool() {
+                        return self.block(body, env);
+                    }
+                }
+                if let Some(else_body) = else_body {
+                    return self.block(else_body, env);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                while self.expr(cond, env)?.as_bool() {
+                    if let Flow::Return(value) = self.block(body, env)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+            }
+            // same as C's `for (int i = start; i < end; i += step)`: end and step
+            // are evaluated again on every iteration
+            StmtKind::For { var, start, end, step, body, .. } => {
+                let start = self.expr(start, env)?;
+                env.scopes.push(HashMap::new());
+                env.declare(&var.name, start);
+                let result = self.for_loop(&var.name, end, step.as_ref(), body, env);
+                env.scopes.pop();
+                return result;
+            }
+            StmtKind::Return { value } => {
+                let value = match value {
+                    Some(value) => self.expr(value, env)?,
+                    None => Value::Void,
+                };
+                return Ok(Flow::Return(value));
+            }
+            StmtKind::Match { scrutinee, arms } => {
+                let value = self.expr(scrutinee, env)?;
+                let (pattern, body) = arms.iter().find(|(pattern, _)| matches(pattern, &value)).expect("checked program: exhaustive match");
+                env.scopes.push(HashMap::new());
+                bind(pattern, value, env);
+                let flow = self.block(body, env);
+                env.scopes.pop();
+                return flow;
+            }
+            StmtKind::Expr(e) => {
+                self.expr(e, env)?;
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn for_loop(&mut self, var : &str, end : &Expr, step : Option<&Expr>, body : &[Stmt], env : &mut Env) -> Result<Flow, Diagnostic> {
+        loop {
+            let end = self.expr(end, env)?.as_int();
+            if env.get(var).as_int() >= end {
+                return Ok(Flow::Next);
+            }
+            if let Flow::Return(value) = self.block(body, env)? {
+                return Ok(Flow::Return(value));
+            }
+            let step = match step {
+                Some(step) => self.expr(step, env)?.as_int(),
+                None => 1,
+            };
+            let next = env.get(var).as_int().wrapping_add(step);
+            env.set(var, Value::Int(next));
+        }
+    }
+
+    // resolves the target of a Set; `path` gets the field indices, outermost first
+    fn place(&mut self, target : &Expr, path : &mut Vec<usize>, env : &mut Env) -> Result<Place, Diagnostic> {
+        match &target.kind {
+            ExprKind::Var { name, .. } => Ok(Place::Var(name.clone())),
+            ExprKind::Index { array, index } => {
+                let items = self.expr(array, env)?;
+                let i = self.expr(index, env)?.as_int();
+                let items = Rc::clone(items.as_array());
+                let i = in_bounds(i, items.borrow().len(), index.span)?;
+                Ok(Place::Item(items, i))
+            }
+            ExprKind::Field { base, index, .. } => {
+                let place = self.place(base, path, env)?;
+                path.push(*index);
+                Ok(place)
+            }
+            _ => unreachable!("checked program: assignment to a place"),
+        }
+    }
+
+    fn expr(&mut self, e : &Expr, env : &mut Env) -> Result<Value, Diagnostic> {
+        Ok(match &e.kind {
+            ExprKind::Int(value) => Value::Int(*value),
+            ExprKind::Float(value) => Value::Float(*value as f64),
+            ExprKind::Str(value) => Value::Str(value.clone()),
+            ExprKind::Bool(value) => Value::Bool(*value),
+            ExprKind::Var { name, .. } => env.get(name).clone(),
+            ExprKind::Array(elements) => {
+                let mut items = vec![];
+                for element in elements {
+                    items.push(self.expr(element, env)?);
+                }
+                Value::Array(Rc::new(RefCell::new(items)))
+            }
+            ExprKind::StructLit { name, fields } => {
+                let mut values = vec![];
+                for (_, value) in fields {
+                    values.push(self.expr(value, env)?);
+                }
+                Value::Struct(Rc::clone(&self.structs[&name.name]), values)
+            }
+            ExprKind::Variant { enum_name, args, index, .. } => {
+                let mut payload = vec![];
+                for arg in args {
+                    payload.push(self.expr(arg, env)?);
+                }
+                Value::Enum(Rc::clone(&self.enums[&enum_name.name]), *index, payload)
+            }
+            ExprKind::Match { scrutinee, arms, .. } => {
+                let value = self.expr(scrutinee, env)?;
+                let (pattern, result) = arms.iter().find(|(pattern, _)| matches(pattern, &value)).expect("checked program: exhaustive match");
+                env.scopes.push(HashMap::new());
+                bind(pattern, value, env);
+                let result = self.expr(result, env);
+                env.scopes.pop();
+                result?
+            }
+            ExprKind::Field { base, index, .. } => match self.expr(base, env)? {
+                Value::Struct(_, mut fields) => fields.swap_remove(*index),
+                value => unreachable!("checked program: expected struct, got {:?}", value),
+            },
+            ExprKind::Index { array, index } => {
+                let items = self.expr(array, env)?;
+                let i = self.expr(index, env)?.as_int();
+                let items = items.as_array().borrow();
+                items[in_bounds(i, items.len(), index.span)?].clone()
+            }
+            ExprKind::Unary { op, operand, .. } => {
+                let value = self.expr(operand, env)?;
+                match (op, value) {
+                    (UnaryOp::Not, value) => Value::Bool(!value.as_bool()),
+                    (UnaryOp::Neg, Value::Int(i)) => Value::Int(i.wrapping_neg()),
+                    (UnaryOp::Neg, value) => Value::Float(-value.as_float()),
+                }
+            }
+            ExprKind::Binary { op : BinOp::And, left, right, .. } => {
+                Value::Bool(self.expr(left, env)?.as_bool() && self.expr(right, env)?.as_bool())
+            }
+            ExprKind::Binary { op : BinOp::Or, left, right, .. } => {
+                Value::Bool(self.expr(left, env)?.as_bool() || self.expr(right, env)?.as_bool())
+            }
+            ExprKind::Binary { op, op_span, left, right } => {
+                let l = self.expr(left, env)?;
+                let r = self.expr(right, env)?;
+                binary(*op, l, r, *op_span)?
+            }
+            ExprKind::Call { name, args } if let Some(builtin) = Builtin::from_name(&name.name) => {
+                let mut values = vec![];
+                for arg in args {
+                    values.push(self.expr(arg, env)?);
+                }
+                if builtin == Builtin::Format {
+                    return Ok(Value::Str(format_print(values[0].as_str(), &values[1..])));
+                }
+                if builtin.signature().is_some_and(|(params, _)| params.is_empty()) {
+                    return self.read(builtin, e.span);
+                }
+                if matches!(builtin, Builtin::ReadFile | Builtin::ReadLines | Builtin::WriteFile | Builtin::AppendFile | Builtin::FileExists) {
+                    return file_builtin(builtin, &values, e.span);
+                }
+                if let Value::Str(_) = values[0] {
+                    return string_builtin(builtin, &values, e.span);
+                }
+                let mut items = values[0].as_array().borrow_mut();
+                match builtin {
+                    Builtin::Len => Value::Int(items.len() as i32),
+                    Builtin::Push => {
+                        items.push(values[1].clone());
+                        Value::Void
+                    }
+                    Builtin::Pop => match items.pop() {
+                        Some(item) => item,
+                        None => return Err(runtime_error("Can't pop from an empty array", e.span)),
+                    },
+                    _ => unreachable!("checked program: {:?} takes a string", builtin),
+                }
+            }
+            ExprKind::Call { name, args } => {
+                let mut values = vec![];
+                for arg in args {
+                    values.push(self.expr(arg, env)?);
+                }
+                let function = Rc::clone(&self.functions[&name.name]);
+                self.call(&function, values, e.span)?
+            }
+        })
+    }
+}
+
+fn matches(pattern : &Pattern, value : &Value) -> bool {
+    match (pattern, value) {
+        (Pattern::Wildcard(_), _) => true,
+        (Pattern::Variant { index, .. }, Value::Enum(_, variant, _)) => index == variant,
+        (_, value) => unreachable!("checked program: expected enum, got {:?}", value),
+    }
+}
+
+// declares the variables of a matching pattern in the innermost scope
+fn bind(pattern : &Pattern, value : Value, env : &mut Env) {
+    if let (Pattern::Variant { bindings, .. }, Value::Enum(_, _, payload)) = (pattern, value) {
+        for (binding, value) in bindings.iter().zip(payload) {
+            if binding.name.name != "_" {
+                env.declare(&binding.name.name, value);
+            }
+        }
+    }
+}
+
+fn field_at<'a>(value : &'a mut Value, path : &[usize]) -> &'a mut Value {
+    path.iter().fold(value, |value, i| match value {
+        Value::Struct(_, fields) => &mut fields[*i],
+        value => unreachable!("checked program: expected struct, got {:?}", value),
+    })
+}
+
+// the builtins whose first argument is a string. They work on bytes and give
+// the same results and errors as the C runtime
+fn string_builtin(builtin : Builtin, values : &[Value], span : Span) -> Result<Value, Diagnostic> {
+    let s = values[0].as_str();
+    let is_space = |c : char| c == ' ' || ('\t'..='\r').contains(&c);
+    Ok(match builtin {
+        Builtin::Len => Value::Int(s.len() as i32),
+        Builtin::Substr => {
+            let (start, count) = (values[1].as_int(), values[2].as_int());
+            let len = s.len() as i32;
+            if start < 0 || count < 0 || start > len - count {
+                return Err(runtime_error(&format!("substr({}, {}) is out of range for a string of length {}", start, count, len), span));
+            }
+            let (start, end) = (start as usize, (start + count) as usize);
+            if !s.is_char_boundary(start) || !s.is_char_boundary(end) {
+                return Err(runtime_error(&format!("substr({}, {}) would split a UTF-8 character", start, count), span));
+            }
+            Value::Str(s[start..end].to_string())
+        }
+        Builtin::Find => Value::Int(s.find(values[1].as_str()).map_or(-1, |i| i as i32)),
+        Builtin::Replace => {
+            let from = values[1].as_str();
+            if from.is_empty() {
+                Value::Str(s.to_string())
+            } else {
+                Value::Str(s.replace(from, values[2].as_str()))
+            }
+        }
+        Builtin::Split => {
+            let sep = values[1].as_str();
+            if sep.is_empty() {
+                return Err(runtime_error("Can't split on an empty separator", span));
+            }
+            let parts = s.split(sep).map(|part| Value::Str(part.to_string())).collect();
+            Value::Array(Rc::new(RefCell::new(parts)))
+        }
+        Builtin::Trim => Value::Str(s.trim_matches(is_space).to_string()),
+        Builtin::ToUpper => Value::Str(s.to_ascii_uppercase()),
+        Builtin::ToLower => Value::Str(s.to_ascii_lowercase()),
+        Builtin::StartsWith => Value::Bool(s.starts_with(values[1].as_str())),
+        Builtin::EndsWith => Value::Bool(s.ends_with(values[1].as_str())),
+        _ => unreachable!("checked program: {:?} doesn't take a string", builtin),
+    })
+}
+
+// the errors say why like the C runtime does with strerror, which is what
+// io::Error shows before " (os error N)"
+fn file_builtin(builtin : Builtin, values : &[Value], span : Span) -> Result<Value, Diagnostic> {
+    let path = values[0].as_str();
+    let failed = |verb : &str, e : io::Error| {
+        let reason = e.to_string();
+        let reason = reason.split(" (os error").next().unwrap_or_default();
+        runtime_error(&format!("Can't {} '{}': {}", verb, path, reason), span)
+    };
+    Ok(match builtin {
+        Builtin::ReadFile | Builtin::ReadLines => {
+            let bytes = fs::read(path).map_err(|e| failed("read", e))?;
+            let contents = String::from_utf8_lossy(&bytes).into_owned();
+            if builtin == Builtin::ReadFile {
+                return Ok(Value::Str(contents));
+            }
+            let body = contents.strip_suffix('\n').unwrap_or(&contents);
+            let lines = if contents.is_empty() {
+                vec![]
+            } else {
+                body.split('\n').map(|line| Value::Str(line.strip_suffix('\r').unwrap_or(line).to_string())).collect()
+            };
+            Value::Array(Rc::new(RefCell::new(lines)))
+        }
+        Builtin::WriteFile => {
+            fs::write(path, values[1].as_str()).map_err(|e| failed("write", e))?;
+            Value::Void
+        }
+        Builtin::AppendFile => {
+            let mut file = OpenOptions::new().append(true).create(true).open(path).map_err(|e| failed("write", e))?;
+            file.write_all(values[1].as_str().as_bytes()).map_err(|e| failed("write", e))?;
+            Value::Void
+        }
+        Builtin::FileExists => Value::Bool(File::open(path).is_ok()),
+        _ => unreachable!("checked program: {:?} isn't a file builtin", builtin),
+    })
+}
+
+fn is_space_byte(b : u8) -> bool {
+    b == b' ' || (b'\t'..=b'\r').contains(&b)
+}
+
+// None at the end of the input
+fn next_line(input : &mut impl BufRead) -> Option<String> {
+    let mut line = vec![];
+    if input.read_until(b'\n', &mut line).unwrap_or(0) == 0 {
+        return None;
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Some(String::from_utf8_lossy(&line).into_owned())
+}
+
+// the whitespace after the word is left for the next read. None at the end of
+// the input
+fn next_word(input : &mut impl BufRead) -> Option<String> {
+    let peek = |input : &mut dyn BufRead| input.fill_buf().ok().and_then(|buf| buf.first().copied());
+    while let Some(b) = peek(input) && is_space_byte(b) {
+        input.consume(1);
+    }
+    let mut word = vec![];
+    while let Some(b) = peek(input) && !is_space_byte(b) {
+        word.push(b);
+        input.consume(1);
+    }
+    if word.is_empty() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&word).into_owned())
+}
+
+// accepts the same words as fl_parse_int and fl_parse_float
+fn parse_number(name : &str, word : &str, ty : &Type, span : Span) -> Result<Value, Diagnostic> {
+    let digits = word.strip_prefix(['+', '-']).unwrap_or(word);
+    let value = if *ty == Type::Int {
+        digits.bytes().all(|b| b.is_ascii_digit()).then(|| word.parse::<i32>().ok()).flatten().map(Value::Int)
+    } else {
+        let (mantissa, exponent) = match digits.find(['e', 'E']) {
+            Some(i) => (&digits[..i], Some(&digits[i + 1..])),
+            None => (digits, None),
+        };
+        let exponent_ok = exponent.is_none_or(|e| {
+            let e = e.strip_prefix(['+', '-']).unwrap_or(e);
+            !e.is_empty() && e.bytes().all(|b| b.is_ascii_digit())
+        });
+        let mantissa_ok = mantissa.bytes().any(|b| b.is_ascii_digit())
+            && mantissa.bytes().all(|b| b.is_ascii_digit() || b == b'.')
+            && mantissa.bytes().filter(|b| *b == b'.').count() <= 1;
+        (mantissa_ok && exponent_ok).then(|| word.parse::<f32>().ok()).flatten().map(|f| Value::Float(f as f64))
+    };
+    value.ok_or_else(|| {
+        let expected = if *ty == Type::Int { "an int" } else { "a float" };
+        runtime_error(&format!("{}() expected {}, but got '{}'", name, expected, word), span)
+    })
+}
+
+// i as an index into an array of length len
+fn in_bounds(i : i32, len : usize, span : Span) -> Result<usize, Diagnostic> {
+    usize::try_from(i).ok().filter(|&i| i < len).ok_or_else(|| {
+        runtime_error(&format!("Index {} is out of bounds for an array of length {}", i, len), span)
+    })
+}
+
+fn binary(op : BinOp, l : Value, r : Value, span : Span) -> Result<Value, Diagnostic> {
+    if let (Value::Int(a), Value::Int(b)) = (&l, &r) {
+        let (a, b) = (*a, *b);
+        if b == 0 && matches!(op, BinOp::Div | BinOp::Mod) {
+            return Err(runtime_error("Division by zero", span));
+        }
+        return Ok(match op {
+            BinOp::Add => Value::Int(a.wrapping_add(b)),
+            BinOp::Sub => Value::Int(a.wrapping_sub(b)),
+            BinOp::Mul => Value::Int(a.wrapping_mul(b)),
+            BinOp::Div => Value::Int(a.wrapping_div(b)),
+            BinOp::Mod => Value::Int(a.wrapping_rem(b)),
+            _ => Value::Bool(compare(op, a.cmp(&b))),
+        });
+    }
+
+    if matches!(l, Value::Int(_) | Value::Float(_)) {
+        // in single precision, like C does with two floats or an int and a float
+        let (a, b) = (l.as_float() as f32, r.as_float() as f32);
+        return Ok(match op {
+            BinOp::Add => Value::Float((a + b) as f64),
+            BinOp::Sub => Value::Float((a - b) as f64),
+            BinOp::Mul => Value::Float((a * b) as f64),
+            BinOp::Div => Value::Float((a / b) as f64),
+            _ => match a.partial_cmp(&b) {
+                Some(ordering) => Value::Bool(compare(op, ordering)),
+                None => Value::Bool(op == BinOp::Ne), // NaN
+            },
+        });
+    }
+
+    // byte by byte, like strcmp
+    if let (Value::Str(a), Value::Str(b)) = (&l, &r) {
+        if op == BinOp::Add {
+            return Ok(Value::Str(format!("{}{}", a, b)));
+        }
+        return Ok(Value::Bool(compare(op, a.as_bytes().cmp(b.as_bytes()))));
+    }
+
+    // bools only have == and !=
+    Ok(Value::Bool((l == r) == (op == BinOp::Eq)))
+}
+
+fn compare(op : BinOp, ordering : std::cmp::Ordering) -> bool {
+    match op {
+        BinOp::Eq => ordering.is_eq(),
+        BinOp::Ne => ordering.is_ne(),
+        BinOp::Lt => ordering.is_lt(),
+        BinOp::Le => ordering.is_le(),
+        BinOp::Gt => ordering.is_gt(),
+        _ => ordering.is_ge(),
+    }
+}
+
+// fills the placeholders of a checked print format the way the generated printf does
+pub fn format_print(format : &str, values : &[Value]) -> String {
+    let mut out = String::new();
+    let mut values = values.iter();
+    for part in format::parse(format).expect("checked program: valid format") {
+        match part {
+            Part::Text(text) => out.push_str(&text),
+            Part::Placeholder(spec) => {
+                if let Some(value) = values.next() {
+                    out.push_str(&format_value(&spec, value));
+                }
+            }
+        }
+    }
+    out
+}
+
+fn format_value(spec : &Spec, value : &Value) -> String {
+    let text = match (spec.kind, value) {
+        (Kind::Hex, Value::Int(i)) => format!("{:x}", *i as u32),
+        (Kind::Binary, Value::Int(i)) => format!("{:b}", *i as u32),
+        (Kind::Exp, Value::Float(f)) if f.is_finite() => c_exp(*f, spec.precision.unwrap_or(6)),
+        (_, Value::Float(f)) if f.is_finite() && let Some(precision) = spec.precision => format!("{:.*}", precision, f),
+        (_, value) => value.to_c_string(),
+    };
+    // printf pads to a number of bytes
+    let padding = " ".repeat(spec.width.unwrap_or(0).saturating_sub(text.len()));
+    if spec.left_aligned(matches!(value, Value::Int(_) | Value::Float(_))) {
+        text + &padding
+    } else {
+        padding + &text
+    }
+}
+
+// printf's %e, whose exponent has a sign and at least two digits
+fn c_exp(f : f64, precision : usize) -> String {
+    let s = format!("{:.*e}", precision, f);
+    let (mantissa, exponent) = s.split_once('e').expect("Rust writes an exponent");
+    let exponent : i32 = exponent.parse().expect("Rust writes a valid exponent");
+    format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+}
@@ -1,9 +1,15 @@
 use ordered_float::OrderedFloat;
 
+use crate::diagnostic::{self,Diagnostic};
+use crate::source::Span;
+
 #[derive(Clone, Copy, Debug,PartialEq,Hash,Eq)]
 
 pub enum TokenType {
     PRINT,
+    PRINTLN,
+    EPRINT, // print to stderr
+    EPRINTLN,
 
     MULT, // multiple *
     MIN, //minus -
@@ -15,10 +21,13 @@ pub enum TokenType {
     RParen,
     LCurly,
     RCurly,
+    LBracket,
+    RBracket,
 
     STRING,
     INT,
     FLOAT,
+    BOOL,
 
     LET,
     IDENTIFIER,
@@ -26,20 +35,36 @@ pub enum TokenType {
 
     SEM, //semicolon
     COMMA,
+    DOT, // field access
+    PATH, // :: as in Shape::Circle
+    ARROW, // => in match arms
+    UNDERSCORE, // _ on its own, in patterns
     Annotation, //type annotation :
 
     // by "Key" i mean keyword
     StringKey,
     IntKey,
     FloatKey,
+    BoolKey,
     
     MAIN,
     FUN, // function
+    STRUCT,
+    ENUM,
+    MATCH,
+    RETURN,
 
     FOR,
 
     EqualsEquals, // ==
-    NotEquals, // =!
+    NotEquals, // !=
+    LessThan, // <
+    LessEquals, // <=
+    GreaterThan, // >
+    GreaterEquals, // >=
+    AND, // &&
+    OR, // ||
+    NOT, // !
     IF,
     ELSE,
     ELF, // else if
@@ -55,28 +80,39 @@ pub enum TokenValue {
     FLOAT(OrderedFloat<f32>),
     STRING(String),
     IDENTIFIER(String),
+    BOOL(bool),
     NONE
 }
 
+#[derive(Clone, Copy, Debug, PartialEq,Hash,Eq)]
+pub enum TriviaKind {
+    LineComment, // `// ...`
+    BlockComment, // `/* ... */`, may nest
+}
+
+// source text that is not a token but is kept so tools can reproduce it
+#[derive(Clone, Debug, PartialEq,Hash,Eq)]
+pub struct Trivia {
+    pub kind : TriviaKind,
+    pub text : String, // including the comment markers
+    pub span : Span,
+}
+
 #[derive(Clone, Debug, PartialEq,Hash,Eq)]
 pub struct Token {
     pub tok_type : TokenType,
-    pub tok_value : TokenValue
+    pub tok_value : TokenValue,
+    pub span : Span,
+    pub trivia : Vec<Trivia> // comments that come right before this token
 }
 
 impl Token {
-    pub fn new(tok_type : TokenType,tok_value : TokenValue) -> Self {
+    pub fn new(tok_type : TokenType,tok_value : TokenValue,span : Span) -> Self {
         Self {
             tok_type,
-            tok_value
-        }
-    }
-
-    pub fn extract_num(&self) -> Option<OrderedFloat<f32>> {
-        match self.tok_value{
-            TokenValue::INT(i) => Some(ordered_float::OrderedFloat(i as f32)),
-            TokenValue::FLOAT(i) => Some(i),
-            _ => None,
+            tok_value,
+            span,
+            trivia : vec![]
         }
     }
 
@@ -87,146 +123,276 @@ impl Token {
             _ => None,
         }
     }
-
-
-    pub fn ops_str(&self) -> String{
-        if self.tok_type == TokenType::MIN {
-            return String::from("-");
-        }
-        else if self.tok_type == TokenType::PLUS{
-            return String::from("+");
-        }
-        else if self.tok_type == TokenType::DIV{
-            return String::from("/");
-        }
-        else if self.tok_type == TokenType::MULT{
-            return String::from("*");
-        }
-        else if self.tok_type == TokenType::MOD{
-            return String::from("%");
-        }
-        else if self.tok_type == TokenType::EqualsEquals{
-            return String::from("==");
-        }
-        else if self.tok_type == TokenType::NotEquals{
-            return String::from("!=");
-        }
-        return String::new();
-    }
 }
 
 pub struct Scanner {
     pub toks : Vec<Token>,
     code : Vec<char>,
     pos : usize,
-    current_char :  char
+    current_char :  char,
+    byte_pos : usize,
+    line : usize,
+    col : usize,
+    tok_start : Span,
+    trivia : Vec<Trivia>,
+    file : usize, // goes into every span, see Span::file
 }
 
 
 impl Scanner {
     fn new_token(&self) -> bool {
-        return self.current_char.is_alphabetic() || self.current_char.is_digit(10) || self.current_char == '_';
+        return self.current_char.is_alphabetic() || self.current_char.is_ascii_digit() || self.current_char == '_';
     }
     pub fn new(input : String) -> Self {
         let inp : Vec<char> = input.chars().collect();
         Self {
             toks: vec![],
-            code: inp.clone(),
+            current_char : inp.first().copied().unwrap_or('\0'),
+            code: inp,
             pos: 0,
-            current_char : inp[0]
+            byte_pos: 0,
+            line: 1,
+            col: 1,
+            tok_start: Span::new(0, 0, 1, 1),
+            trivia: vec![],
+            file: 0,
         }
     }
 
+    // for the REPL, which scans each input as its own file
+    pub fn in_file(mut self, file : usize) -> Self {
+        self.file = file;
+        self.tok_start = self.tok_start.in_file(file);
+        self
+    }
+
     fn advance(&mut self) {
-        self.pos += 1;
-        if self.pos < self.code.len() {
-            self.current_char = self.code[self.pos];
+        if self.pos >= self.code.len() {
+            return;
         }
+        self.byte_pos += self.current_char.len_utf8();
+        if self.current_char == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        self.pos += 1;
+        self.current_char = self.code.get(self.pos).copied().unwrap_or('\0');
+    }
+
+    fn peek(&self) -> char {
+        self.code.get(self.pos + 1).copied().unwrap_or('\0')
+    }
+
+    // remembers where the token that is about to be scanned begins
+    fn mark(&mut self) {
+        self.tok_start = Span::new(self.byte_pos, self.byte_pos, self.line, self.col).in_file(self.file);
+    }
+
+    fn span(&self) -> Span {
+        Span { end : self.byte_pos, ..self.tok_start }
     }
 
     fn push(&mut self,tok_type : TokenType,tok_value : TokenValue) {
-        self.toks.push(Token::new(tok_type, tok_value));
+        let mut tok = Token::new(tok_type, tok_value, self.span());
+        tok.trivia = std::mem::take(&mut self.trivia);
+        self.toks.push(tok);
+    }
+
+    fn push_trivia(&mut self, kind : TriviaKind, start : usize) {
+        let text : String = self.code[start..self.pos].iter().collect();
+        let span = self.span();
+        self.trivia.push(Trivia { kind, text, span });
+    }
+
+    fn line_comment(&mut self) {
+        let start = self.pos;
+        while self.pos < self.code.len() && self.current_char != '\n' {
+            self.advance();
+        }
+        self.push_trivia(TriviaKind::LineComment, start);
     }
 
-    pub fn tokenize(&mut self) {
+    fn block_comment(&mut self) -> Result<(), Diagnostic> {
+        let start = self.pos;
+        let mut depth = 0;
         while self.pos < self.code.len() {
-            if self.current_char.is_whitespace() || self.current_char == '\n' {
+            if self.current_char == '/' && self.peek() == '*' {
+                depth += 1;
                 self.advance();
-            } else if self.current_char == '+' {
-                self.push(TokenType::PLUS, TokenValue::NONE);
+            } else if self.current_char == '*' && self.peek() == '/' {
+                depth -= 1;
                 self.advance();
-            } else if self.current_char == '*' {
-                self.push(TokenType::MULT, TokenValue::NONE);
+            }
+            self.advance();
+            if depth == 0 {
+                self.push_trivia(TriviaKind::BlockComment, start);
+                return Ok(());
+            }
+        }
+        Err(Diagnostic::error(diagnostic::UNTERMINATED_COMMENT, "Unterminated block comment".to_string())
+            .with_span(Span { end : self.tok_start.start + 2, ..self.tok_start }))
+    }
+
+    fn single(&mut self,tok_type : TokenType) {
+        self.advance();
+        self.push(tok_type, TokenValue::NONE);
+    }
+
+    // decodes the escape sequence starting at the current '\\'
+    fn escape(&mut self) -> Result<char, Diagnostic> {
+        let start = Span::new(self.byte_pos, self.byte_pos, self.line, self.col).in_file(self.file);
+        self.advance();
+        let esc = self.current_char;
+        self.advance();
+        let decoded = match esc {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '\\' => Some('\\'),
+            '"' => Some('"'),
+            'u' if self.current_char == '{' => {
                 self.advance();
-            } else if self.current_char == '-' {
-                self.push(TokenType::MIN, TokenValue::NONE);
+                let mut hex = String::new();
+                while self.current_char.is_ascii_hexdigit() {
+                    hex.push(self.current_char);
+                    self.advance();
+                }
+                let closed = self.current_char == '}';
+                if closed {
+                    self.advance();
+                }
+                if !closed || hex.is_empty() || hex.len() > 6 {
+                    None
+                } else {
+                    u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                }
+            }
+            _ => None,
+        };
+        let span = Span { end : self.byte_pos, ..start };
+
+        match decoded {
+            Some('\0') => Err(Diagnostic::error(diagnostic::INVALID_ESCAPE, "Strings cannot contain a NUL character".to_string())
+                .with_span(span)),
+            Some(c) => Ok(c),
+            None => Err(Diagnostic::error(diagnostic::INVALID_ESCAPE, "Invalid escape sequence".to_string())
+                .with_span(span)
+                .with_note("valid escapes are \\n, \\t, \\r, \\\\, \\\" and \\u{...} with 1 to 6 hex digits".to_string())),
+        }
+    }
+
+    pub fn tokenize(&mut self) -> Result<(), Diagnostic> {
+        while self.pos < self.code.len() {
+            self.mark();
+            if self.current_char.is_whitespace() {
                 self.advance();
+            } else if self.current_char == '+' {
+                self.single(TokenType::PLUS);
+            } else if self.current_char == '*' {
+                self.single(TokenType::MULT);
+            } else if self.current_char == '-' {
+                self.single(TokenType::MIN);
             } else if self.current_char == '=' {
-                if self.code[self.pos+1] == '=' {
-                    self.push(TokenType::EqualsEquals, TokenValue::NONE);
+                if self.peek() == '=' {
                     self.advance();
+                    self.single(TokenType::EqualsEquals);
+                }
+                else if self.peek() == '>' {
                     self.advance();
+                    self.single(TokenType::ARROW);
+                }
+                else {
+                    self.single(TokenType::EQUALS);
                 }
-                else if self.code[self.pos+1] == '!' {
-                    self.push(TokenType::NotEquals, TokenValue::NONE);
+            } else if self.current_char == '!' {
+                if self.peek() == '=' {
                     self.advance();
+                    self.single(TokenType::NotEquals);
+                }
+                else {
+                    self.single(TokenType::NOT);
+                }
+            } else if self.current_char == '<' {
+                if self.peek() == '=' {
                     self.advance();
+                    self.single(TokenType::LessEquals);
                 }
                 else {
-                    self.push(TokenType::EQUALS, TokenValue::NONE);
+                    self.single(TokenType::LessThan);
+                }
+            } else if self.current_char == '>' {
+                if self.peek() == '=' {
                     self.advance();
+                    self.single(TokenType::GreaterEquals);
                 }
-            } else if self.current_char == '/' {
-                self.push(TokenType::DIV, TokenValue::NONE);
+                else {
+                    self.single(TokenType::GreaterThan);
+                }
+            } else if self.current_char == '&' && self.peek() == '&' {
                 self.advance();
-            } else if self.current_char == '(' {
-                self.push(TokenType::LParen, TokenValue::NONE);
+                self.single(TokenType::AND);
+            } else if self.current_char == '|' && self.peek() == '|' {
                 self.advance();
+                self.single(TokenType::OR);
+            } else if self.current_char == '/' && self.peek() == '/' {
+                self.line_comment();
+            } else if self.current_char == '/' && self.peek() == '*' {
+                self.block_comment()?;
+            } else if self.current_char == '/' {
+                self.single(TokenType::DIV);
+            } else if self.current_char == '(' {
+                self.single(TokenType::LParen);
             } else if self.current_char == ')' {
-                self.push(TokenType::RParen, TokenValue::NONE);
-                self.advance();
+                self.single(TokenType::RParen);
             } else if self.current_char == '{' {
-                self.push(TokenType::LCurly, TokenValue::NONE);
-                self.advance();
+                self.single(TokenType::LCurly);
             } else if self.current_char == '}' {
-                self.push(TokenType::RCurly, TokenValue::NONE);
-                self.advance();
+                self.single(TokenType::RCurly);
+            } else if self.current_char == '[' {
+                self.single(TokenType::LBracket);
+            } else if self.current_char == ']' {
+                self.single(TokenType::RBracket);
             } else if self.current_char == ';' {
-                self.push(TokenType::SEM, TokenValue::NONE);
+                self.single(TokenType::SEM);
+            } else if self.current_char == ':' && self.peek() == ':' {
                 self.advance();
+                self.single(TokenType::PATH);
             } else if self.current_char == ':' {
-                self.push(TokenType::Annotation, TokenValue::NONE);
-                self.advance();
+                self.single(TokenType::Annotation);
+            } else if self.current_char == '_' && !(self.peek().is_alphanumeric() || self.peek() == '_') {
+                self.single(TokenType::UNDERSCORE);
             } else if self.current_char == '%' {
-                self.push(TokenType::MOD, TokenValue::NONE);
-                self.advance();
+                self.single(TokenType::MOD);
             }else if self.current_char == ',' {
-                self.push(TokenType::COMMA, TokenValue::NONE);
-                self.advance();
-            }  else if self.current_char.is_digit(10) {
+                self.single(TokenType::COMMA);
+            } else if self.current_char == '.' {
+                self.single(TokenType::DOT);
+            }  else if self.current_char.is_ascii_digit() {
                 let mut num = String::new();
-                let mut is_float = false;
-                while self.current_char.is_digit(10) || self.current_char == '.' {
+                let mut dots = 0;
+                while self.current_char.is_ascii_digit() || self.current_char == '.' {
                     if self.current_char == '.' {
-                        if is_float {
-                            panic!("float didn't have two dots");
-                        }
-                        else {
-                            is_float = true;
-                        }
+                        dots += 1;
                     }
                     num.push(self.current_char);
                     self.advance();
-                    if self.pos >= self.code.len() {
-                        break;
-                    }
                 }
-                if is_float {
-                    let value = num.parse::<f32>().expect("Failed to parse number");
+                if dots > 1 {
+                    return Err(Diagnostic::error(diagnostic::MALFORMED_NUMBER, format!("Number '{}' has more than one '.'", num))
+                        .with_span(self.span()));
+                }
+                if dots == 1 {
+                    let value = num.parse::<f32>().map_err(|e| {
+                        Diagnostic::error(diagnostic::MALFORMED_NUMBER, format!("Invalid float '{}': {}", num, e)).with_span(self.span())
+                    })?;
                     self.push(TokenType::FLOAT, TokenValue::FLOAT(ordered_float::OrderedFloat(value)));
                 }
                 else {
-                    let value = num.parse::<i32>().expect("Failed to parse number");
+                    let value = num.parse::<i32>().map_err(|e| {
+                        Diagnostic::error(diagnostic::MALFORMED_NUMBER, format!("Invalid integer '{}': {}", num, e)).with_span(self.span())
+                    })?;
                     self.push(TokenType::INT, TokenValue::INT(value));
                 }
             }
@@ -236,67 +402,123 @@ impl Scanner {
                     alph.push(self.current_char);
                     self.advance();
                 }
-                if alph == String::from("print") {
-                    self.push(TokenType::PRINT,TokenValue::NONE);
-                }
-                else if alph == String::from("let") {
-                    self.push(TokenType::LET,TokenValue::NONE);
-                }
-                else if alph == String::from("main") {
-                    self.push(TokenType::MAIN,TokenValue::NONE);
-                }
-                else if alph == String::from("fun") {
-                    self.push(TokenType::FUN,TokenValue::NONE);
-                }
-                else if alph == String::from("int") {
-                    self.push(TokenType::IntKey,TokenValue::NONE);
-                }
-                else if alph == String::from("float") {
-                    self.push(TokenType::FloatKey,TokenValue::NONE);
-                }
-                else if alph == String::from("string") {
-                    self.push(TokenType::StringKey,TokenValue::NONE);
-                }
-                else if alph == String::from("for") {
-                    self.push(TokenType::FOR,TokenValue::NONE);
-                }
-                else if alph == String::from("if") {
-                    self.push(TokenType::IF,TokenValue::NONE);
-                }
-                else if alph == String::from("elf") {
-                    self.push(TokenType::ELF,TokenValue::NONE);
-                }
-                else if alph == String::from("else") {
-                    self.push(TokenType::ELSE,TokenValue::NONE);
+                let tok_type = match alph.as_str() {
+                    "print" => TokenType::PRINT,
+                    "println" => TokenType::PRINTLN,
+                    "eprint" => TokenType::EPRINT,
+                    "eprintln" => TokenType::EPRINTLN,
+                    "let" => TokenType::LET,
+                    "main" => TokenType::MAIN,
+                    "fun" => TokenType::FUN,
+                    "struct" => TokenType::STRUCT,
+                    "enum" => TokenType::ENUM,
+                    "match" => TokenType::MATCH,
+                    "return" => TokenType::RETURN,
+                    "int" => TokenType::IntKey,
+                    "float" => TokenType::FloatKey,
+                    "string" => TokenType::StringKey,
+                    "bool" => TokenType::BoolKey,
+                    "true" | "false" => TokenType::BOOL,
+                    "for" => TokenType::FOR,
+                    "if" => TokenType::IF,
+                    "elf" => TokenType::ELF,
+                    "else" => TokenType::ELSE,
+                    "while" => TokenType::WHILE,
+                    _ => TokenType::IDENTIFIER,
+                };
+                if tok_type == TokenType::IDENTIFIER {
+                    self.push(TokenType::IDENTIFIER,TokenValue::IDENTIFIER(alph));
                 }
-                else if alph == String::from("while") {
-                    self.push(TokenType::WHILE,TokenValue::NONE);
+                else if tok_type == TokenType::BOOL {
+                    self.push(TokenType::BOOL,TokenValue::BOOL(alph == "true"));
                 }
                 else {
-                    self.push(TokenType::IDENTIFIER,TokenValue::IDENTIFIER(alph));
+                    self.push(tok_type,TokenValue::NONE);
                 }
             }
             else if self.current_char == '"' {
                 let mut string = String::new();
 
                 self.advance();
-                while self.current_char != '"' && self.current_char != '\0'{
-                    string.push(self.current_char);
-                    self.advance();
+                while self.current_char != '"' && self.pos < self.code.len() {
+                    if self.current_char == '\\' {
+                        string.push(self.escape()?);
+                    } else {
+                        string.push(self.current_char);
+                        self.advance();
+                    }
                 }
                 
-                if self.current_char == '\0' {
-                    panic!("Unterminated string literal");
+                if self.pos >= self.code.len() {
+                    return Err(Diagnostic::error(diagnostic::UNTERMINATED_STRING, "Unterminated string literal".to_string())
+                        .with_span(self.span()));
                 }
 
                 self.advance();
                 self.push(TokenType::STRING, TokenValue::STRING(string));
             }
             else {
-                panic!("Unknown character: {}", self.current_char);
+                self.advance();
+                return Err(Diagnostic::error(diagnostic::UNKNOWN_CHARACTER, format!("Unknown character: '{}'", self.code[self.pos - 1]))
+                    .with_span(self.span()));
             }
         }
+        self.mark();
         self.push(TokenType::EOF, TokenValue::NONE);
+        Ok(())
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(text : &str) -> Result<Vec<Token>, Diagnostic> {
+        let mut scanner = Scanner::new(text.to_string());
+        scanner.tokenize()?;
+        Ok(scanner.toks)
+    }
+
+    fn string(text : &str) -> Result<String, Diagnostic> {
+        let toks = scan(text)?;
+        Ok(toks[0].extract_str().expect("a string token"))
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(string(r#""a\nb\tc\rd""#).unwrap(), "a\nb\tc\rd");
+        assert_eq!(string(r#""\\ \"q\"""#).unwrap(), "\\ \"q\"");
+        assert_eq!(string(r#""\u{41}\u{e9}\u{1F600}""#).unwrap(), "A\u{e9}\u{1F600}");
+        assert_eq!(string(r#""héllo""#).unwrap(), "héllo");
+    }
+
+    #[test]
+    fn rejects_bad_escapes() {
+        for text in [r#""\q""#, r#""\u{}""#, r#""\u{1234567}""#, r#""\u{D800}""#, r#""\u{41""#, r#""\u41""#, r#""\u{0}""#] {
+            let error = string(text).expect_err(text);
+            assert_eq!(error.code, diagnostic::INVALID_ESCAPE, "{}", text);
+        }
+        assert_eq!(string(r#""abc"#).unwrap_err().code, diagnostic::UNTERMINATED_STRING);
+    }
+
+    #[test]
+    fn keeps_comments_as_trivia() {
+        let toks = scan("// line\nx /* block */ y").unwrap();
+        assert_eq!(toks[0].trivia.len(), 1);
+        assert_eq!(toks[0].trivia[0].kind, TriviaKind::LineComment);
+        assert_eq!(toks[0].trivia[0].text, "// line");
+        assert_eq!(toks[1].trivia[0].kind, TriviaKind::BlockComment);
+        assert_eq!(toks[1].trivia[0].text, "/* block */");
+    }
+
+    #[test]
+    fn nests_block_comments() {
+        let toks = scan("/* a /* b */ c */ x").unwrap();
+        assert_eq!(toks.len(), 2); // x and EOF
+        assert_eq!(toks[0].tok_value, TokenValue::IDENTIFIER("x".to_string()));
+        assert_eq!(toks[0].trivia[0].text, "/* a /* b */ c */");
+        assert_eq!(scan("/* a /* b */ x").unwrap_err().code, diagnostic::UNTERMINATED_COMMENT);
+        assert_eq!(scan("/* /* */ */").unwrap()[0].tok_type, TokenType::EOF);
+    }
+}
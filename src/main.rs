@@ -1,9 +1,165 @@
+#![allow(clippy::upper_case_acronyms, clippy::needless_return)]
+
+mod cc;
+mod checker;
+mod codegen;
+mod diagnostic;
+mod driver;
+mod escape;
+mod format;
+mod interp;
 mod lexer;
 mod paser;
 mod nodes;
-use paser::run;
+mod repl;
+mod runtime;
+mod source;
+mod visit;
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use cc::CcOptions;
+use driver::{Command,Emit,Options};
+use runtime::Memory;
+
+const USAGE : &str = "usage: funlang <command> <file.fun> [options]
+       funlang repl
+
+commands:
+    build    compile a program (to an executable unless --emit says otherwise)
+    run      compile a program and run it (or interpret it with --interpret)
+    check    report errors without generating any code
+    repl     type in statements and expressions and run them as you go
+
+options:
+    -o <path>        where to write the output of build
+    --emit=<kind>    what build produces: tokens, ast (as JSON), c or exe
+    -O<level>        C optimization level: 0, 1, 2, 3 or s
+    -l <lib>         link an extra library
+    -L <dir>         add a library search directory
+    --memory=<mode>  how compiled programs allocate: gc (Boehm GC, the default),
+                     arena (freed at exit) or malloc (never freed), both without libgc
+    --interpret      run: evaluate the program directly instead of compiling it
+    -h, --help       print this message
+
+The C compiler is taken from $CC, or else the first of cc, gcc and clang in PATH.
+";
+
+fn parse_args(args : &[String]) -> Result<Options, String> {
+    let mut args = args.iter();
 
+    let command = match args.next().map(|s| s.as_str()) {
+        Some("build") => Command::Build,
+        Some("run") => Command::Run,
+        Some("check") => Command::Check,
+        Some(other) => return Err(format!("unknown command '{}'", other)),
+        None => return Err("missing command".to_string()),
+    };
+
+    let mut input = None;
+    let mut output = None;
+    let mut emit = None;
+    let mut interpret = false;
+    let mut memory_set = false;
+    let mut cc = CcOptions::default();
+
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            match args.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => return Err("'-o' needs a path".to_string()),
+            }
+        }
+        else if let Some(kind) = arg.strip_prefix("--emit=") {
+            emit = Some(match kind {
+                "tokens" => Emit::Tokens,
+                "ast" => Emit::Ast,
+                "c" => Emit::C,
+                "exe" => Emit::Exe,
+                _ => return Err(format!("unknown emit kind '{}'", kind)),
+            });
+        }
+        else if let Some(mode) = arg.strip_prefix("--memory=") {
+            match Memory::from_name(mode) {
+                Some(memory) => cc.memory = memory,
+                None => return Err(format!("unknown memory mode '{}'", mode)),
+            }
+            memory_set = true;
+        }
+        else if arg == "--interpret" {
+            interpret = true;
+        }
+        else if let Some(level) = arg.strip_prefix("-O") {
+            if !["0", "1", "2", "3", "s"].contains(&level) {
+                return Err(format!("unknown optimization level '{}'", arg));
+            }
+            cc.opt_level = Some(level.to_string());
+        }
+        else if let Some(lib) = arg.strip_prefix("-l") {
+            match if lib.is_empty() { args.next().map(|s| s.as_str()) } else { Some(lib) } {
+                Some(lib) => cc.libs.push(lib.to_string()),
+                None => return Err("'-l' needs a library name".to_string()),
+            }
+        }
+        else if let Some(dir) = arg.strip_prefix("-L") {
+            match if dir.is_empty() { args.next().map(|s| s.as_str()) } else { Some(dir) } {
+                Some(dir) => cc.lib_dirs.push(dir.to_string()),
+                None => return Err("'-L' needs a directory".to_string()),
+            }
+        }
+        else if arg.starts_with('-') {
+            return Err(format!("unknown option '{}'", arg));
+        }
+        else if input.is_none() {
+            input = Some(PathBuf::from(arg));
+        }
+        else {
+            return Err(format!("unexpected argument '{}'", arg));
+        }
+    }
+
+    if command != Command::Build && (output.is_some() || emit.is_some()) {
+        return Err("'-o' and '--emit' can only be used with build".to_string());
+    }
+    if command != Command::Run && interpret {
+        return Err("'--interpret' can only be used with run".to_string());
+    }
+    if memory_set && (command == Command::Check || interpret) {
+        return Err("'--memory' only applies to compiled programs".to_string());
+    }
+
+    Ok(Options {
+        command,
+        input : input.ok_or("missing input file")?,
+        output,
+        emit : emit.unwrap_or(Emit::Exe),
+        interpret,
+        cc,
+    })
+}
 
 fn main() {
-    run();
+    let args : Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        print!("{}", USAGE);
+        return;
+    }
+
+    // the repl has no input file, so it doesn't go through parse_args
+    let parsed = match args.first().map(|s| s.as_str()) {
+        Some("repl") if args.len() > 1 => Err(format!("unexpected argument '{}'", args[1])),
+        Some("repl") => process::exit(repl::run()),
+        _ => parse_args(&args),
+    };
+
+    let code = match parsed {
+        Ok(options) => driver::execute(&options),
+        Err(message) => {
+            eprint!("error: {}\n\n{}", message, USAGE);
+            2
+        }
+    };
+    process::exit(code);
 }
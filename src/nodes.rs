@@ -1,778 +1,481 @@
-use crate::lexer::{TokenType,Token};
-use std::any::Any;
+use std::fmt;
 
-#[derive(Debug)]
-pub struct VarInfo {
-    pub tok_type : TokenType,
-    pub is_pointer: bool,
-}
+use serde::Serialize;
 
-impl VarInfo {
-    pub fn new( tok_type : TokenType,is_pointer : bool) -> Self {
-        Self { tok_type,is_pointer }
-    }
-}
-pub trait Node {
-    fn as_any(&self) -> &dyn Any;
-    fn generate(&self) -> String;
-    fn c_format(&self) -> String;
-    fn is_number(&self) -> bool;
-    fn get_type(&self) -> TokenType;
-    fn c_type(&self) -> String;
-    fn is_pure_value(&self) -> bool;
-}
+use crate::lexer::TokenType;
+use crate::source::Span;
 
-pub struct BinOpNode {
-    left: Box<dyn Node>,
-    bin_op: Token,
-    right: Box<dyn Node>,
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub enum Type {
+    Int,
+    Float,
+    String,
+    Bool,
+    Array(Box<Type>),
+    Named(String), // a struct or an enum
+    Optional(Box<Type>), // `int?`, only made by the try_read builtins, see EnumDef::optional
+    Void, // functions without a return type
 }
 
-impl BinOpNode {
-    pub fn new(left: Box<dyn Node>, bin_op: Token, right: Box<dyn Node>) -> Self {
-        if left.is_number() == !right.is_number() {
-            panic!("Error: left is {:?} and right is {:?}",left.get_type(),right.get_type())
-        }
-        else if left.get_type() == TokenType::STRING && (bin_op.tok_type != TokenType::EqualsEquals && bin_op.tok_type != TokenType::NotEquals) {
-            panic!("Error: you can't do {:?} to {:?}",bin_op.tok_type,left.get_type())
-        }
-        BinOpNode { left, bin_op, right }
+impl Type {
+    pub fn is_numeric(&self) -> bool {
+        *self == Type::Int || *self == Type::Float
     }
-}
 
-impl Node for BinOpNode {
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-    
-    fn generate(&self) -> String {
-        return format!("{}{}{}",self.left.generate(),self.bin_op.ops_str(),self.right.generate());
-    }
-    fn c_format(&self) -> String {
-        let left_fmt = self.left.c_format();
-        let right_fmt = self.right.c_format();
-
-
-        if left_fmt == "%f" || right_fmt == "%f" {
-            "%f".to_string()
-        } else if left_fmt == "%d" && right_fmt == "%d" {
-            "%d".to_string()
-        } else {
-            "%s".to_string()
+    pub fn element(&self) -> Option<&Type> {
+        match self {
+            Type::Array(element) => Some(element),
+            _ => None,
         }
     }
-    fn is_number(&self) -> bool {
-        self.left.is_number() && self.right.is_number()
-    }
-    fn get_type(&self) -> TokenType {
-        if self.left.get_type() == TokenType::FLOAT || self.right.get_type() == TokenType::FLOAT {
-            return TokenType::FLOAT;
-        }
-        TokenType::INT
-    }
-    fn c_type(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "int",
-            TokenType::FLOAT => "float",
-            TokenType::STRING => "char*",
-            _ => "int",
-        }.to_string();
-    }
-    fn is_pure_value(&self) -> bool {
-        true
-    }
-}
-
-
-pub struct NumberNode {
-    token: Token,
 }
 
-impl NumberNode {
-    pub fn new(token: Token) -> Self {
-        NumberNode { token }
-    }
-}
-
-impl Node for NumberNode {
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-    fn generate(&self) -> String {
-        return format!("{}",self.token.extract_num().unwrap());
-    }
-    fn c_format(&self) -> String {
-        if self.token.tok_type == TokenType::FLOAT {
-            return "%f".to_string();
-        }
-        else if self.token.tok_type == TokenType::INT {
-            return "%d".to_string();
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::String => write!(f, "string"),
+            Type::Bool => write!(f, "bool"),
+            Type::Array(element) => write!(f, "[{}]", element),
+            Type::Named(name) => write!(f, "{}", name),
+            Type::Optional(inner) => write!(f, "{}?", inner),
+            Type::Void => write!(f, "void"),
         }
-        return "%d".to_string();
-    }
-    fn is_number(&self) -> bool {
-        true
-    }
-    fn get_type(&self) -> TokenType{
-        return self.token.tok_type;
     }
-
-    fn c_type(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "int",
-            TokenType::FLOAT => "float",
-            TokenType::STRING => "char*",
-            _ => "int",
-        }.to_string();
-    }
-
-    fn is_pure_value(&self) -> bool {
-        true
-    }
-}
-
-pub struct StringNode {
-    token: Token,
-}
-
-impl StringNode {
-    pub fn new(token: Token) -> Self {
-        StringNode { token }
-    }
-
 }
 
-impl Node for StringNode {
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-
-    fn generate(&self) -> String {
-        return format!("\"{}\"",self.token.extract_str().unwrap());
-    }
-    fn c_format(&self) -> String {
-        return "%s".to_string();
-    }
-    fn is_number(&self) -> bool {
-        false
-    }
-    fn get_type(&self) -> TokenType {
-        return TokenType::STRING;
-    }
-    
-    fn c_type(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "int",
-            TokenType::FLOAT => "float",
-            TokenType::STRING => "char*",
-            _ => "int",
-        }.to_string();
-    }
-
-    fn is_pure_value(&self) -> bool {
-        true
-    }
-}
-
-
-pub struct PrintNode {
-    nodes : Vec<Box<dyn Node>>,
-}
-
-impl PrintNode {
-    pub fn new(nodes: Vec<Box<dyn Node>>) -> Self {
-        PrintNode { nodes }
-    }
-}
-
-impl Node for PrintNode {
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-
-    fn generate(&self) -> String {
-        if self.nodes.is_empty() {
-            return "printf(\"\\n\");\n".to_string();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinOp {
+    pub fn from_token(tok_type : TokenType) -> Option<BinOp> {
+        Some(match tok_type {
+            TokenType::PLUS => BinOp::Add,
+            TokenType::MIN => BinOp::Sub,
+            TokenType::MULT => BinOp::Mul,
+            TokenType::DIV => BinOp::Div,
+            TokenType::MOD => BinOp::Mod,
+            TokenType::EqualsEquals => BinOp::Eq,
+            TokenType::NotEquals => BinOp::Ne,
+            TokenType::LessThan => BinOp::Lt,
+            TokenType::LessEquals => BinOp::Le,
+            TokenType::GreaterThan => BinOp::Gt,
+            TokenType::GreaterEquals => BinOp::Ge,
+            TokenType::AND => BinOp::And,
+            TokenType::OR => BinOp::Or,
+            _ => return None,
+        })
+    }
+
+    // the same in FunLang and C
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Mod => "%",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
         }
-
-        let format_node = &self.nodes[0];
-        let format_string = if let Some(str_node) = format_node.as_any().downcast_ref::<StringNode>() {
-            match str_node.token.extract_str() {
-                Some(s) => s,
-                None => panic!("Expected STRING token but found something else"),
-            }
-        } else {
-            panic!("First argument to print must be a string literal");
-        };
-
-        let mut c_format_str = String::new();
-        let mut arg_formats = vec![];
-        let args = &self.nodes[1..];
-        let mut placeholder_count = 0;
-
-        let mut chars = format_string.chars().peekable();
-        while let Some(ch) = chars.next() {
-            if ch == '{' && chars.peek() == Some(&'}') {
-                chars.next(); 
-                if placeholder_count >= args.len() {
-                    panic!("Not enough arguments for format placeholders");
-                }
-                let fmt = args[placeholder_count].c_format();
-                c_format_str.push_str(&fmt);
-                arg_formats.push(fmt);
-                placeholder_count += 1;
-            } else {
-                c_format_str.push(ch);
-            }
-        }
-
-        if placeholder_count != args.len() {
-            panic!(
-                "Mismatched number of placeholders and arguments: expected {}, got {}",
-                placeholder_count,
-                args.len()
-            );
-        }
-
-        if arg_formats.is_empty() {
-            return format!("printf(\"{}\");\n", c_format_str);
-        }
-
-        let formatted_args = arg_formats.iter().zip(args.iter()).map(|(_, arg_node)| {
-                format!("{}", arg_node.generate())
-        }).collect::<Vec<_>>().join(", ");
-
-        format!("printf(\"{}\", {});\n", c_format_str, formatted_args)
     }
 
-
-    fn c_format(&self) -> String {
-        "%s".to_string()
+    pub fn is_comparison(self) -> bool {
+        matches!(self, BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge)
     }
 
-    fn is_number(&self) -> bool {
-        false
-    }
-
-    fn get_type(&self) -> TokenType {
-        TokenType::STRING
-    }
-
-    fn c_type(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "int",
-            TokenType::FLOAT => "float",
-            TokenType::STRING => "char*",
-            _ => "int",
-        }.to_string();
-    }
-
-    fn is_pure_value(&self) -> bool {
-       false 
+    pub fn is_logical(self) -> bool {
+        matches!(self, BinOp::And | BinOp::Or)
     }
 }
 
-pub struct UnaryOpNode {
-    op_tok : Token,
-    node : Box<dyn Node>
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum UnaryOp {
+    Neg,
+    Not,
 }
 
-impl UnaryOpNode {
-    pub fn new( op_tok : Token, node : Box<dyn Node>) -> Self{
-        Self {
-            op_tok,
-            node
+impl UnaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "!",
         }
     }
 }
 
-
-pub struct VarDeclNode {
-    name : Token,
-    node : Box<dyn Node>,
-    declared_type: Option<TokenType>,
-}
-
-impl VarDeclNode{
-    pub fn new(name: Token, node : Box<dyn Node>,declared_type : Option<TokenType> ) -> Self {
-        VarDeclNode { name, node, declared_type }
-    }
-}
-
-
-impl Node for VarDeclNode{
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-    fn generate(&self) -> String {
-
-        let name_str = match self.name.extract_str() {
-            Some(s) => s,
-            None => {
-                eprintln!("Error: Variable name token does not contain an identifier string!");
-                eprintln!("self.name token: {:?}", self.name);
-                panic!("Cannot generate variable assignment without valid identifier name.");
-            }
-        };
-
-        if self.declared_type == None {
-            return match self.node.get_type() {
-                TokenType::STRING => format!("char* {} = (char*) GC_MALLOC(strlen({}) + 1);\nstrcpy({}, {});\n",name_str,self.node.generate(),name_str,self.node.generate()),
-                _ => format!("{}* {} =  ({}*) GC_MALLOC(sizeof({}));\n*{} = {};\n",self.c_type(),name_str,self.c_type(),self.c_type(),name_str,self.node.generate())
-            };
+// functions every program can call. They can't be redefined, so a call by
+// one of these names always means the builtin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    Len, // of an array or a string
+    Push,
+    Pop,
+    Substr,
+    Find,
+    Replace,
+    Split,
+    Trim,
+    ToUpper,
+    ToLower,
+    StartsWith,
+    EndsWith,
+    ReadLine,
+    ReadInt,
+    ReadFloat,
+    TryReadLine, // like ReadLine, but None at the end of the input
+    TryReadInt,
+    TryReadFloat,
+    ReadFile,
+    ReadLines, // the lines of a file, without their line breaks
+    WriteFile,
+    AppendFile,
+    FileExists,
+    Format, // like print, but returns the text
+}
+
+impl Builtin {
+    pub fn from_name(name : &str) -> Option<Builtin> {
+        match name {
+            "len" => Some(Builtin::Len),
+            "push" => Some(Builtin::Push),
+            "pop" => Some(Builtin::Pop),
+            "substr" => Some(Builtin::Substr),
+            "find" => Some(Builtin::Find),
+            "replace" => Some(Builtin::Replace),
+            "split" => Some(Builtin::Split),
+            "trim" => Some(Builtin::Trim),
+            "to_upper" => Some(Builtin::ToUpper),
+            "to_lower" => Some(Builtin::ToLower),
+            "starts_with" => Some(Builtin::StartsWith),
+            "ends_with" => Some(Builtin::EndsWith),
+            "read_line" => Some(Builtin::ReadLine),
+            "read_int" => Some(Builtin::ReadInt),
+            "read_float" => Some(Builtin::ReadFloat),
+            "try_read_line" => Some(Builtin::TryReadLine),
+            "try_read_int" => Some(Builtin::TryReadInt),
+            "try_read_float" => Some(Builtin::TryReadFloat),
+            "read_file" => Some(Builtin::ReadFile),
+            "read_lines" => Some(Builtin::ReadLines),
+            "write_file" => Some(Builtin::WriteFile),
+            "append_file" => Some(Builtin::AppendFile),
+            "file_exists" => Some(Builtin::FileExists),
+            "format" => Some(Builtin::Format),
+            _ => None,
         }
-        if self.declared_type.unwrap() != self.node.get_type() {
-            panic!("Error : defirent type {:?} -> {:?}",self.declared_type.unwrap(),self.node.get_type());
-        }
-        return match self.declared_type.unwrap() {
-            TokenType::STRING => format!("char* {} = (char*) GC_MALLOC(strlen({}) + 1);\nstrcpy({}, {});\n",name_str,self.node.generate(),name_str,self.node.generate()),
-            TokenType::FLOAT => format!("float* {} =  (float*) GC_MALLOC(sizeof(float));\n*{} = {};\n",name_str,name_str,self.node.generate()),
-            TokenType::INT => format!("int* {} =  (int*) GC_MALLOC(sizeof(int));\n*{} = {};\n",name_str,name_str,self.node.generate()),
-            _ => {
-                panic!("Error : unknow type")
-            }
-        };
-    }
-
-    fn c_format(&self) -> String {
-        self.node.c_format()
-    }
-    fn get_type(&self) -> TokenType {
-        self.node.get_type()
     }
-    fn is_number(&self) -> bool {
-        self.node.is_number()
-    }
-
-
-    fn c_type(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "int",
-            TokenType::FLOAT => "float",
-            TokenType::STRING => "char*",
-            _ => "int",
-        }.to_string();
-    }
-
-    fn is_pure_value(&self) -> bool {
-       false 
-    }
-
-}
 
-pub struct VarAssignNode {
-    name : Token,
-    node : Box<dyn Node>
-}
-
-impl VarAssignNode {
-    pub fn new(name: Token, node : Box<dyn Node>) -> Self {
-        VarAssignNode { name,node }
-    }
-}
-
-
-impl Node for VarAssignNode {
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-    fn generate(&self) -> String {
-
-        let name_str = match self.name.extract_str() {
-            Some(s) => s,
-            None => {
-                eprintln!("Error: Variable name token does not contain an identifier string!");
-                eprintln!("self.name token: {:?}", self.name);
-                panic!("Cannot generate variable assignment without valid identifier name.");
-            }
+    // parameter and return types of the builtins that aren't generic over arrays
+    pub fn signature(self) -> Option<(Vec<Type>, Type)> {
+        use Type::{Bool, Float, Int, String as Str};
+        let optional = |ty : Type| Type::Optional(Box::new(ty));
+        Some(match self {
+            Builtin::Substr => (vec![Str, Int, Int], Str),
+            Builtin::Find => (vec![Str, Str], Int),
+            Builtin::Replace => (vec![Str, Str, Str], Str),
+            Builtin::Split => (vec![Str, Str], Type::Array(Box::new(Str))),
+            Builtin::Trim | Builtin::ToUpper | Builtin::ToLower => (vec![Str], Str),
+            Builtin::StartsWith | Builtin::EndsWith => (vec![Str, Str], Bool),
+            Builtin::ReadLine => (vec![], Str),
+            Builtin::ReadInt => (vec![], Int),
+            Builtin::ReadFloat => (vec![], Float),
+            Builtin::TryReadLine => (vec![], optional(Str)),
+            Builtin::TryReadInt => (vec![], optional(Int)),
+            Builtin::TryReadFloat => (vec![], optional(Float)),
+            Builtin::ReadFile => (vec![Str], Str),
+            Builtin::ReadLines => (vec![Str], Type::Array(Box::new(Str))),
+            Builtin::WriteFile | Builtin::AppendFile => (vec![Str, Str], Type::Void),
+            Builtin::FileExists => (vec![Str], Bool),
+            Builtin::Len | Builtin::Push | Builtin::Pop | Builtin::Format => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Ident {
+    pub name : String,
+    pub span : Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Expr {
+    pub kind : ExprKind,
+    pub span : Span,
+    pub ty : Type, // filled in by the checker, Void until then
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum ExprKind {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Bool(bool),
+    Array(Vec<Expr>), // the type of an empty one comes from the variable it is assigned to
+    Var {
+        name : String,
+        c_name : String, // the name in C, filled in by the checker
+    },
+    Unary {
+        op : UnaryOp,
+        op_span : Span,
+        operand : Box<Expr>,
+    },
+    Binary {
+        op : BinOp,
+        op_span : Span,
+        left : Box<Expr>,
+        right : Box<Expr>,
+    },
+    Index {
+        array : Box<Expr>,
+        index : Box<Expr>,
+    },
+    // listed as written until the checker puts them in declaration order
+    StructLit {
+        name : Ident,
+        fields : Vec<(Ident, Expr)>,
+    },
+    Field {
+        base : Box<Expr>,
+        field : Ident,
+        index : usize, // position in the struct declaration, filled in by the checker
+    },
+    // `Shape::Circle(r)`, or `Shape::Empty` without a payload
+    Variant {
+        enum_name : Ident,
+        variant : Ident,
+        args : Vec<Expr>,
+        index : usize, // position in the enum declaration, filled in by the checker
+    },
+    Match {
+        scrutinee : Box<Expr>,
+        arms : Vec<(Pattern, Expr)>,
+        id : usize, // numbers the match expressions of a program, filled in by the checker
+    },
+    Call {
+        name : Ident,
+        args : Vec<Expr>,
+    },
+}
+
+impl Expr {
+    pub fn new(kind : ExprKind, span : Span) -> Self {
+        let ty = match kind {
+            ExprKind::Int(_) => Type::Int,
+            ExprKind::Float(_) => Type::Float,
+            ExprKind::Str(_) => Type::String,
+            ExprKind::Bool(_) => Type::Bool,
+            _ => Type::Void,
         };
-
-        return match self.node.get_type() {
-           TokenType::STRING => format!("{} = (char*) GC_MALLOC(strlen({}) + 1);\nstrcpy({}, {});\n",name_str,self.node.generate(),name_str,self.node.generate()),
-            _ => format!("*{} = {};\n",name_str,self.node.generate())
-        };
-
-    }
-    fn c_format(&self) -> String {
-        self.node.c_format()
-    }
-    fn get_type(&self) -> TokenType {
-        self.node.get_type()
-    }
-    fn is_number(&self) -> bool {
-        self.node.is_number()
-    }
-    fn c_type(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "int",
-            TokenType::FLOAT => "float",
-            TokenType::STRING => "char*",
-            _ => "int",
-        }.to_string();
-    }
-
-    fn is_pure_value(&self) -> bool {
-       false 
-    }
-}
-impl Node for UnaryOpNode {
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-
-    fn generate(&self) -> String {
-        return format!("{}{}",self.op_tok.ops_str(),self.node.generate());
-    }
-    fn c_format(&self) -> String {
-        return self.node.c_format();
-    }
-
-    fn is_number(&self) -> bool {
-        true
-    }
-
-    fn get_type(&self) -> TokenType {
-        self.node.get_type()
-    }
-
-    fn c_type(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "int",
-            TokenType::FLOAT => "float",
-            TokenType::STRING => "char*",
-            _ => "int",
-        }.to_string();
-    }
-    fn is_pure_value(&self) -> bool {
-        true
-    }
-}
-
-pub struct VarAccessNode {
-    name     : Token,
-    var_type : TokenType,
-    is_pointer : bool
-}
-
-impl VarAccessNode {
-    pub fn new(name : Token,var_type: TokenType, is_pointer : bool) -> Self{
-       Self { name,var_type,is_pointer}
-    }
-}
-
-
-impl Node for VarAccessNode{
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-
-    fn generate(&self) -> String {
-        if self.is_pointer {
-            if self.get_type() == TokenType::STRING {
-                return format!("{}",self.name.extract_str().unwrap());
-            }
-            return format!("(*{})",self.name.extract_str().unwrap());
-        }
-        self.name.extract_str().unwrap()
-    }
-    fn c_format(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "%i",
-            TokenType::FLOAT => "%f",
-            TokenType::STRING => "%s",
-            _ => "int",
-        }.to_string();
-    }
-
-    fn is_number(&self) -> bool {
-        true
-    }
-
-    fn get_type(&self) -> TokenType {
-        return self.var_type;
-    }
-
-    fn c_type(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "int",
-            TokenType::FLOAT => "float",
-            TokenType::STRING => "char*",
-            _ => "int",
-        }.to_string();
-    }
-
-    fn is_pure_value(&self) -> bool {
-        true
-    }
-}
-
-pub struct MainFuncNode {
-    nodes : Vec<Box<dyn Node>>
-}
-
-impl MainFuncNode {
-    pub fn new(nodes : Vec<Box<dyn Node>>) -> Self {
-        Self {
-            nodes
-        }
-    }
-}
-
-impl Node for MainFuncNode {
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-
-    fn generate(&self) -> String {
-        let mut ge= String::new();
-        for g in &self.nodes {
-            ge.push_str(&g.generate());
-        }
-        return format!("int main() {{\nGC_INIT();\n{}return 0;\n}}\n",ge);
-
-    }
-    fn c_format(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "%i",
-            TokenType::FLOAT => "%f",
-            TokenType::STRING => "%s",
-            _ => "int",
-        }.to_string();
-    }
-
-    fn is_number(&self) -> bool {
-        true
-    }
-
-    fn get_type(&self) -> TokenType {
-        return TokenType::INT;
-    }
-
-    fn c_type(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "int",
-            TokenType::FLOAT => "float",
-            TokenType::STRING => "char*",
-            _ => "int",
-        }.to_string();
-    }
-    fn is_pure_value(&self) -> bool {
-       false 
-    }
-}
-
-pub struct ForLoopNode {
-    start : Box<dyn Node>,
-    end : Box<dyn Node>,
-    var : Token,
-    nodes : Vec<Box<dyn Node>>,
-    step : Option<Box<dyn Node>>,
-}
-
-impl ForLoopNode {
-    pub fn new( start : Box<dyn Node>, end : Box<dyn Node>,var : Token, nodes : Vec<Box<dyn Node>>, step : Option<Box<dyn Node>>) -> Self {
-        if start.get_type() != TokenType::INT && end.get_type() != TokenType::INT {
-            panic!("Error : start : {:?}, end : {:?}",start.get_type(),end.get_type())
-        }
-        if !step.is_none(){
-            if step.as_ref().unwrap().get_type() != TokenType::INT {
-                panic!("Error : step : {:?}",step.as_ref().unwrap().get_type())
-            }
-        }
-        Self { start, end,var,step,nodes}
-    }
-}
-
-impl Node for ForLoopNode {
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-
-
-    fn generate(&self) -> String {
-        let mut body_code = String::new();
-        for stmt in &self.nodes {
-            body_code.push_str(&stmt.generate());
+        Self { kind,span,ty }
+    }
+
+    pub fn var(name : Ident) -> Self {
+        let c_name = name.name.clone();
+        Expr::new(ExprKind::Var { name : name.name, c_name }, name.span)
+    }
+}
+
+// where print and println write, and eprint and eprintln
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stmt {
+    pub kind : StmtKind,
+    pub span : Span,
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum StmtKind {
+    Print {
+        format : String,
+        format_span : Span,
+        args : Vec<Expr>,
+        stream : Stream,
+        newline : bool, // println and eprintln
+    },
+    Let {
+        name : Ident,
+        declared_type : Option<Type>,
+        value : Expr,
+        c_name : String,
+        // for a let that creates an array: whether the array is still reachable
+        // after the let's block, set by the escape pass
+        escapes : Option<bool>,
+    },
+    Assign {
+        name : Ident,
+        value : Expr,
+        c_name : String,
+    },
+    // `a[i] = value` or `p.x = value`; plain variables use Assign
+    Set {
+        target : Expr,
+        value : Expr,
+    },
+    // `if` followed by any number of `elf`s, in order
+    If {
+        branches : Vec<(Expr, Vec<Stmt>)>,
+        else_body : Option<Vec<Stmt>>,
+    },
+    While {
+        cond : Expr,
+        body : Vec<Stmt>,
+    },
+    For {
+        var : Ident,
+        c_var : String,
+        start : Expr,
+        end : Expr,
+        step : Option<Expr>,
+        body : Vec<Stmt>,
+    },
+    Return {
+        value : Option<Expr>,
+    },
+    // the statement form of match, whose arms are blocks
+    Match {
+        scrutinee : Expr,
+        arms : Vec<(Pattern, Vec<Stmt>)>,
+    },
+    Expr(Expr),
+}
+
+impl Stmt {
+    pub fn new(kind : StmtKind, span : Span) -> Self {
+        Self { kind,span }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Param {
+    pub name : Ident,
+    pub ty : Type,
+    pub c_name : String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldDef {
+    pub name : Ident,
+    pub ty : Type,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StructDef {
+    pub name : Ident,
+    pub fields : Vec<FieldDef>,
+    pub span : Span,
+}
+
+impl StructDef {
+    pub fn field(&self, name : &str) -> Option<(usize, &FieldDef)> {
+        self.fields.iter().enumerate().find(|(_, field)| field.name.name == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VariantDef {
+    pub name : Ident,
+    pub payload : Vec<Type>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EnumDef {
+    pub name : Ident,
+    pub variants : Vec<VariantDef>,
+    pub span : Span,
+}
+
+impl EnumDef {
+    // `T?` behaves like this enum. Some comes first, so its tag is 0
+    pub fn optional(inner : &Type) -> EnumDef {
+        let ident = |name : &str| Ident { name : name.to_string(), span : Span::default() };
+        EnumDef {
+            name : ident(&format!("{}?", inner)),
+            variants : vec![
+                VariantDef { name : ident("Some"), payload : vec![inner.clone()] },
+                VariantDef { name : ident("None"), payload : vec![] },
+            ],
+            span : Span::default(),
         }
-
-        let var_name = self.var.extract_str().unwrap();
-        let init_code = format!(
-            "int {0} = {1}",
-            var_name,
-            self.start.generate(),
-        );
-
-        let condition = format!("{} < {}", var_name, self.end.generate());
-
-        let increment = if let Some(step) = &self.step {
-            format!("{} += {}", var_name, step.generate())
-        } else {
-            format!("{}++", var_name)
-        };
-
-        format!(
-            "for ({}; {}; {}) {{\n{}}}\n",
-            init_code, condition, increment, body_code
-        )
-    }
-
-    fn c_format(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "%i",
-            TokenType::FLOAT => "%f",
-            TokenType::STRING => "%s",
-            _ => "int",
-        }.to_string();
-    }
-
-    fn is_number(&self) -> bool {
-        false
-    }
-
-    fn get_type(&self) -> TokenType {
-        return TokenType::NONE;
     }
 
-    fn c_type(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "int",
-            TokenType::FLOAT => "float",
-            TokenType::STRING => "char*",
-            _ => "int",
-        }.to_string();
-    }
-    fn is_pure_value(&self) -> bool {
-       false 
+    pub fn variant(&self, name : &str) -> Option<(usize, &VariantDef)> {
+        self.variants.iter().enumerate().find(|(_, variant)| variant.name.name == name)
     }
 }
 
-pub struct IfNode {
-    node : Box<dyn Node>,
-    body : Vec<Box<dyn Node>>,
-    else_body : Option<Vec<Box<dyn Node>>>,
-    elf_body : Option<Vec<Vec<Box<dyn Node>>>>,
-    elf_node : Option<Vec<Box<dyn Node>>>
+// a variable bound by a match arm; `_` binds nothing
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Binding {
+    pub name : Ident,
+    pub ty : Type, // the payload type, filled in by the checker
+    pub c_name : String,
 }
 
-impl IfNode {
-    pub fn new(node : Box<dyn Node>, body : Vec<Box<dyn Node>>, else_body : Option<Vec<Box<dyn Node>>>, elf_body : Option<Vec<Vec<Box<dyn Node>>>>, elf_node : Option<Vec<Box<dyn Node>>>) -> Self {
-        Self { node,body,else_body,elf_body,elf_node }
-    }
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Pattern {
+    // `Circle(r)` or `Shape::Circle(r)`
+    Variant {
+        enum_name : Option<Ident>,
+        variant : Ident,
+        bindings : Vec<Binding>,
+        index : usize, // filled in by the checker
+    },
+    Wildcard(Span), // `_`
 }
 
-impl Node for IfNode {
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-
-    fn generate(&self) -> String {
-        let mut result = String::new();
-
-        let mut if_body = String::new();
-        for stmt in &self.body {
-            if_body.push_str(&stmt.generate());
-        }
-
-        result.push_str(&format!("if ({}) {{\n{}\n}}", self.node.generate(), if_body));
-
-        if let (Some(conditions), Some(bodies)) = (&self.elf_node, &self.elf_body) {
-            for (cond, body_group) in conditions.iter().zip(bodies) {
-                let mut body_code = String::new();
-                for stmt in body_group {
-                    body_code.push_str(&stmt.generate());
-                }
-                result.push_str(&format!(" else if ({}) {{\n{}\n}}", cond.generate(), body_code));
-            }
-        }
-
-        if let Some(else_body) = &self.else_body {
-            let mut else_code = String::new();
-            for stmt in else_body {
-                else_code.push_str(&stmt.generate());
-            }
-            result.push_str(&format!(" else {{\n{}\n}}", else_code));
+impl Pattern {
+    pub fn span(&self) -> Span {
+        match self {
+            Pattern::Variant { variant, bindings, .. } => match bindings.last() {
+                Some(last) => variant.span.to(last.name.span),
+                None => variant.span,
+            },
+            Pattern::Wildcard(span) => *span,
         }
-
-        result
-    }
-
-
-    fn c_format(&self) -> String {
-        "%s".to_string()
-    }
-
-    fn is_number(&self) -> bool {
-        false
-    }
-
-    fn get_type(&self) -> TokenType {
-        TokenType::STRING
-    }
-
-    fn c_type(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "int",
-            TokenType::FLOAT => "float",
-            TokenType::STRING => "char*",
-            _ => "int",
-        }.to_string();
-    }
-
-    fn is_pure_value(&self) -> bool {
-      true 
     }
 }
 
-
-pub struct WhileNode{
-    node : Box<dyn Node>,
-    body : Vec<Box<dyn Node>>,
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Function {
+    pub name : Ident,
+    pub params : Vec<Param>,
+    pub return_type : Type,
+    pub body : Vec<Stmt>,
+    pub span : Span,
 }
 
-impl WhileNode{
-    pub fn new(node : Box<dyn Node>, body : Vec<Box<dyn Node>>) -> Self {
-        Self { node,body }
-    }
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Program {
+    pub structs : Vec<StructDef>,
+    pub enums : Vec<EnumDef>,
+    pub functions : Vec<Function>,
+    pub main : Option<Function>,
 }
-
-impl Node for WhileNode {
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-
-    fn generate(&self) -> String {
-        let mut body = String::new();
-        for stmt in &self.body {
-            body.push_str(&stmt.generate());
-        }
-
-        format!("while ({})\n {{{}}}",self.node.generate(),body)
-    }
-
-
-    fn c_format(&self) -> String {
-        "%s".to_string()
-    }
-
-    fn is_number(&self) -> bool {
-        false
-    }
-
-    fn get_type(&self) -> TokenType {
-        TokenType::STRING
-    }
-
-    fn c_type(&self) -> String {
-        return match self.get_type() {
-            TokenType::INT => "int",
-            TokenType::FLOAT => "float",
-            TokenType::STRING => "char*",
-            _ => "int",
-        }.to_string();
-    }
-
-    fn is_pure_value(&self) -> bool {
-      true 
-    }
-}
-
@@ -1,35 +1,33 @@
-use std::collections::HashMap;
-use std::{fs::File};
-use std::fs;
-use std::io::Write;
 use std::vec;
 
 use crate::nodes::*;
-use crate::lexer::{TokenType,TokenValue,Token,Scanner};
+use crate::lexer::{TokenType,TokenValue,Token};
+use crate::diagnostic::{self,Diagnostic};
+use crate::source::Span;
 
 
 pub struct Parser {
     toks : Vec<Token>,
     pos : usize,
     current_tok : Token,
-    symbol_table: HashMap<String,VarInfo>,
+    repl : bool, // allows bare expressions and a missing `;` at the end of the input
 }
 
 
 
 impl Parser {
     pub fn new(toks: Vec<Token>) -> Self {
-        let current_tok = toks.get(0)
+        let current_tok = toks.first()
             .cloned()
             .unwrap_or_else(|| {
-                Token::new(TokenType::NONE, TokenValue::NONE)
+                Token::new(TokenType::NONE, TokenValue::NONE, Span::default())
             });
        
         Self {
             toks,
             pos: 0,
             current_tok,
-            symbol_table : HashMap::new(),
+            repl : false,
         }
     }
     fn advance(&mut self) {
@@ -38,14 +36,71 @@ impl Parser {
             self.current_tok = self.toks[self.pos].clone();
         }
     }
-    fn factor(&mut self) -> Result<Box<dyn Node>, String> {
-        let mut minus_count = 0;
 
-        while self.current_tok.tok_type == TokenType::PLUS || self.current_tok.tok_type == TokenType::MIN {
-            if self.current_tok.tok_type == TokenType::MIN {
-                minus_count += 1;
+    // span of the token just consumed
+    fn prev_span(&self) -> Span {
+        self.toks[self.pos.saturating_sub(1)].span
+    }
+
+    fn ident(&mut self) -> Result<Ident, Diagnostic> {
+        let tok = self.expect(TokenType::IDENTIFIER)?;
+        Ok(Ident { name : tok.extract_str().unwrap(), span : tok.span })
+    }
+
+    fn peek_type(&self) -> TokenType {
+        self.peek_at(1)
+    }
+
+    fn peek_at(&self, n : usize) -> TokenType {
+        match self.toks.get(self.pos + n) {
+            Some(tok) => tok.tok_type,
+            None => TokenType::EOF,
+        }
+    }
+    fn factor(&mut self) -> Result<Expr, Diagnostic> {
+        if self.current_tok.tok_type == TokenType::PLUS {
+            self.advance();
+            return self.factor();
+        }
+
+        if self.current_tok.tok_type == TokenType::MIN || self.current_tok.tok_type == TokenType::NOT {
+            let op_tok = self.current_tok.clone();
+            self.advance();
+            let operand = self.factor()?;
+            let op = if op_tok.tok_type == TokenType::NOT { UnaryOp::Not } else { UnaryOp::Neg };
+            let span = op_tok.span.to(operand.span);
+            return Ok(Expr::new(ExprKind::Unary { op, op_span : op_tok.span, operand : Box::new(operand) }, span));
+        }
+
+        let mut expr = self.primary()?;
+        loop {
+            if self.current_tok.tok_type == TokenType::LBracket {
+                self.advance();
+                let index = self.expr()?;
+                let rbracket = self.expect(TokenType::RBracket)?;
+                let span = expr.span.to(rbracket.span);
+                expr = Expr::new(ExprKind::Index { array : Box::new(expr), index : Box::new(index) }, span);
+            } else if self.current_tok.tok_type == TokenType::DOT {
+                self.advance();
+                let field = self.ident()?;
+                let span = expr.span.to(field.span);
+                expr = Expr::new(ExprKind::Field { base : Box::new(expr), field, index : 0 }, span);
+            } else {
+                return Ok(expr);
             }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, Diagnostic> {
+        if self.current_tok.tok_type == TokenType::LBracket {
+            let lbracket = self.current_tok.span;
             self.advance();
+            let mut elements = vec![];
+            if self.current_tok.tok_type != TokenType::RBracket {
+                elements = self.parse_print_ags()?;
+            }
+            let rbracket = self.expect(TokenType::RBracket)?;
+            return Ok(Expr::new(ExprKind::Array(elements), lbracket.to(rbracket.span)));
         }
 
         if self.current_tok.tok_type == TokenType::LParen {
@@ -53,79 +108,83 @@ impl Parser {
             let expr = self.expr()?;
 
             if self.current_tok.tok_type != TokenType::RParen {
-                return Err("Expected closing parenthesis".to_string());
+                return Err(Diagnostic::error(diagnostic::UNEXPECTED_TOKEN, "Expected closing parenthesis".to_string()).with_span(self.current_tok.span));
             }
             self.advance();
-
-            if minus_count % 2 == 0 {
-                return Ok(expr);
-            } else {
-                let minus_tok = Token::new(TokenType::MIN, TokenValue::NONE);
-                return Ok(Box::new(UnaryOpNode::new(minus_tok, expr)));
-            }
+            return Ok(expr);
         }
 
-        if self.current_tok.tok_type == TokenType::INT || self.current_tok.tok_type == TokenType::FLOAT {
-            let tok = self.current_tok.clone();
+        let tok = self.current_tok.clone();
+        let literal = match &tok.tok_value {
+            TokenValue::INT(value) => Some(ExprKind::Int(*value)),
+            TokenValue::FLOAT(value) => Some(ExprKind::Float(value.0)),
+            TokenValue::BOOL(value) => Some(ExprKind::Bool(*value)),
+            TokenValue::STRING(value) => Some(ExprKind::Str(value.clone())),
+            _ => None,
+        };
+        if let Some(kind) = literal {
             self.advance();
+            return Ok(Expr::new(kind, tok.span));
+        }
 
-            if minus_count % 2 == 0 {
-                return Ok(Box::new(NumberNode::new(tok)));
-            } else {
-                let minus_tok = Token::new(TokenType::MIN, TokenValue::NONE);
-                return Ok(Box::new(UnaryOpNode::new(minus_tok, Box::new(NumberNode::new(tok)))));
-            }
+        if self.current_tok.tok_type == TokenType::MATCH {
+            return self.parse_match_expr();
         }
 
         if self.current_tok.tok_type == TokenType::IDENTIFIER {
-    let tok = self.current_tok.clone();
-    self.advance();
-
-    let var_name = tok.extract_str().unwrap();
-    let var_info = match self.symbol_table.get(&var_name) {
-        Some(info) => info,
-        None => return Err(format!("Undefined variable: {}", var_name)),
-    };
-
-    let is_pointer = var_info.is_pointer;
-    let var_type = var_info.tok_type;
-
-    if minus_count % 2 == 0 {
-        return Ok(Box::new(VarAccessNode::new(tok, var_type, is_pointer)));
-    } else {
-        let minus_tok = Token::new(TokenType::MIN, TokenValue::NONE);
-        return Ok(Box::new(UnaryOpNode::new(minus_tok, Box::new(VarAccessNode::new(tok, var_type, is_pointer)))));
-    }
-}
-
+            let name = self.ident()?;
 
-        if self.current_tok.tok_type == TokenType::STRING {
-            let val = self.current_tok.clone(); 
-            self.advance();
-            return Ok(Box::new(StringNode::new(val)));
+            if self.current_tok.tok_type == TokenType::PATH {
+                return self.parse_variant(name);
+            }
+            if self.current_tok.tok_type == TokenType::LParen {
+                return self.parse_call(name);
+            }
+            // `Name { field :` can only start a struct literal, so `if x { ... }` is safe
+            if self.current_tok.tok_type == TokenType::LCurly && self.peek_at(1) == TokenType::IDENTIFIER && self.peek_at(2) == TokenType::Annotation {
+                return self.parse_struct_lit(name);
+            }
+            return Ok(Expr::var(name));
         }
 
 
 
-        Err(format!(
+        Err(Diagnostic::error(diagnostic::UNEXPECTED_TOKEN, format!(
             "Unexpected token in factor: {:?}",
             self.current_tok.tok_type
-        ))
+        )).with_span(self.current_tok.span))
     }
 
 
 
-    fn term(&mut self) -> Result<Box<dyn Node>, String> {
+    fn term(&mut self) -> Result<Expr, Diagnostic> {
         self.bin_op(Parser::factor, &[TokenType::MULT, TokenType::DIV,TokenType::MOD])
     }
 
-    fn expr(&mut self) -> Result<Box<dyn Node>, String> {
-        self.bin_op(Parser::term, &[TokenType::PLUS, TokenType::MIN,TokenType::EqualsEquals,TokenType::NotEquals])
+    fn arith(&mut self) -> Result<Expr, Diagnostic> {
+        self.bin_op(Parser::term, &[TokenType::PLUS, TokenType::MIN])
     }
 
-    fn bin_op<F>(&mut self, parse_func: F, ops: &[TokenType]) -> Result<Box<dyn Node>, String>
+    fn comparison(&mut self) -> Result<Expr, Diagnostic> {
+        self.bin_op(Parser::arith, &[TokenType::LessThan, TokenType::LessEquals, TokenType::GreaterThan, TokenType::GreaterEquals])
+    }
+
+    fn equality(&mut self) -> Result<Expr, Diagnostic> {
+        self.bin_op(Parser::comparison, &[TokenType::EqualsEquals, TokenType::NotEquals])
+    }
+
+    fn logic_and(&mut self) -> Result<Expr, Diagnostic> {
+        self.bin_op(Parser::equality, &[TokenType::AND])
+    }
+
+    // lowest precedence first: || < && < == != < < <= > >= < + - < * / % < unary
+    fn expr(&mut self) -> Result<Expr, Diagnostic> {
+        self.bin_op(Parser::logic_and, &[TokenType::OR])
+    }
+
+    fn bin_op<F>(&mut self, parse_func: F, ops: &[TokenType]) -> Result<Expr, Diagnostic>
     where
-        F: Fn(&mut Self) -> Result<Box<dyn Node>, String>,
+        F: Fn(&mut Self) -> Result<Expr, Diagnostic>,
     {
         let mut left = parse_func(self)?;
 
@@ -133,40 +192,47 @@ impl Parser {
             let op_tok = self.current_tok.clone();
             self.advance();
             let right = parse_func(self)?;
-            left = Box::new(BinOpNode::new(left, op_tok, right));
+            let span = left.span.to(right.span);
+            let kind = ExprKind::Binary {
+                op : BinOp::from_token(op_tok.tok_type).unwrap(),
+                op_span : op_tok.span,
+                left : Box::new(left),
+                right : Box::new(right),
+            };
+            left = Expr::new(kind, span);
         }
 
         Ok(left)
     }
-    pub fn expect(&mut self, expected: TokenType) -> Result<Token, String> {
+    pub fn expect(&mut self, expected: TokenType) -> Result<Token, Diagnostic> {
         if self.current_tok.tok_type == expected {
             let tok = self.current_tok.clone();
             self.advance();
             Ok(tok)
         } else {
 
-            Err(format!(
+            Err(Diagnostic::error(diagnostic::UNEXPECTED_TOKEN, format!(
                 "Expected {:?}, but found {:?}",
                 expected, self.current_tok.tok_type
-            ))
+            )).with_span(self.current_tok.span))
         }
     }
-    fn expect_vec(&mut self,expected: Vec<TokenType>) -> Result<Token, String> {
+    fn expect_vec(&mut self,expected: Vec<TokenType>) -> Result<Token, Diagnostic> {
         if expected.contains(&self.current_tok.tok_type) {
             let tok = self.current_tok.clone();
             self.advance();
             Ok(tok)
        } else {
 
-            Err(format!(
+            Err(Diagnostic::error(diagnostic::UNEXPECTED_TOKEN, format!(
                 "Expected {:?}, but found {:?}",
                 expected, self.current_tok.tok_type
-            ))
+            )).with_span(self.current_tok.span))
         }
     }
 
 
-    pub fn parse_print_ags(&mut self) -> Result<Vec<Box<dyn Node>>, String> {
+    pub fn parse_print_ags(&mut self) -> Result<Vec<Expr>, Diagnostic> {
         let mut args = vec![];
         args.push(self.expr()?);
         while self.current_tok.tok_type == TokenType::COMMA {
@@ -177,232 +243,537 @@ impl Parser {
     }
 
 
-    fn parse_main_func(&mut self) -> Box<dyn Node> {
-        self.expect(TokenType::MAIN).expect("Expect Main");
-        self.expect(TokenType::LParen).expect("Expect L_Paren");
-        self.expect(TokenType::RParen).expect("Expect R_Paren");
-        self.expect(TokenType::LCurly).expect("Expect L_Curly");
-        let nodes = self.parse().expect("Error parsing main body");
-        self.expect(TokenType::RCurly).expect("Expect R_Curly");
-        return Box::new(MainFuncNode::new(nodes));
+    fn parse_call(&mut self, name : Ident) -> Result<Expr, Diagnostic> {
+        self.expect(TokenType::LParen)?;
+        let mut args = vec![];
+        if self.current_tok.tok_type != TokenType::RParen {
+            args = self.parse_print_ags()?;
+        }
+        let rparen = self.expect(TokenType::RParen)?;
+        let span = name.span.to(rparen.span);
+
+        Ok(Expr::new(ExprKind::Call { name, args }, span))
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Box<dyn Node>>, String> {
-        let mut statements = vec![];
+    fn parse_struct_lit(&mut self, name : Ident) -> Result<Expr, Diagnostic> {
+        self.expect(TokenType::LCurly)?;
+        let mut fields = vec![];
+        while self.current_tok.tok_type != TokenType::RCurly {
+            let field = self.ident()?;
+            self.expect(TokenType::Annotation)?;
+            let value = self.expr()?;
+            fields.push((field, value));
+
+            if self.current_tok.tok_type != TokenType::COMMA {
+                break;
+            }
+            self.advance();
+        }
+        let rcurly = self.expect(TokenType::RCurly)?;
+        let span = name.span.to(rcurly.span);
 
-        while self.current_tok.tok_type != TokenType::EOF  && self.current_tok.tok_type != TokenType::RCurly {
-            let stmt = if self.current_tok.tok_type == TokenType::PRINT {
-                self.advance(); 
-                self.expect(TokenType::LParen)?; 
-                let args = self.parse_print_ags();
-                self.expect(TokenType::RParen)?;
-                self.expect(TokenType::SEM)?;
-                Box::new(PrintNode::new(args?)) as Box<dyn Node>
+        Ok(Expr::new(ExprKind::StructLit { name, fields }, span))
+    }
+
+    fn parse_variant(&mut self, enum_name : Ident) -> Result<Expr, Diagnostic> {
+        self.expect(TokenType::PATH)?;
+        let variant = self.ident()?;
+        let mut args = vec![];
+        let mut span = enum_name.span.to(variant.span);
+        if self.current_tok.tok_type == TokenType::LParen {
+            self.advance();
+            if self.current_tok.tok_type != TokenType::RParen {
+                args = self.parse_print_ags()?;
             }
+            span = span.to(self.expect(TokenType::RParen)?.span);
+        }
 
-            else if self.current_tok.tok_type == TokenType::LET {
-                self.advance(); 
-                let name = self.expect(TokenType::IDENTIFIER)?;
-
-                let mut var_type = None;
-
-                if let Ok(_) = self.expect(TokenType::Annotation) {
-                    let type_key = self.expect_vec(vec![
-                        TokenType::IntKey,
-                        TokenType::StringKey,
-                        TokenType::FloatKey
-                    ])?;
-
-                    var_type = Some(match type_key.tok_type {
-                        TokenType::StringKey => TokenType::STRING,
-                        TokenType::IntKey => TokenType::INT,
-                        TokenType::FloatKey => TokenType::FLOAT,
-                        _ => TokenType::NONE,
-                    });
+        Ok(Expr::new(ExprKind::Variant { enum_name, variant, args, index : 0 }, span))
+    }
+
+    // `_`, `Circle`, `Circle(r)`, `Shape::Rect(w, _)`
+    fn parse_pattern(&mut self) -> Result<Pattern, Diagnostic> {
+        if self.current_tok.tok_type == TokenType::UNDERSCORE {
+            let span = self.current_tok.span;
+            self.advance();
+            return Ok(Pattern::Wildcard(span));
+        }
+
+        let mut enum_name = None;
+        let mut variant = self.ident()?;
+        if self.current_tok.tok_type == TokenType::PATH {
+            self.advance();
+            enum_name = Some(variant);
+            variant = self.ident()?;
+        }
+        let mut bindings = vec![];
+        if self.current_tok.tok_type == TokenType::LParen {
+            self.advance();
+            while self.current_tok.tok_type != TokenType::RParen {
+                let name = if self.current_tok.tok_type == TokenType::UNDERSCORE {
+                    let span = self.current_tok.span;
+                    self.advance();
+                    Ident { name : "_".to_string(), span }
+                } else {
+                    self.ident()?
+                };
+                let c_name = name.name.clone();
+                bindings.push(Binding { name, ty : Type::Void, c_name });
+
+                if self.current_tok.tok_type != TokenType::COMMA {
+                    break;
                 }
+                self.advance();
+            }
+            self.expect(TokenType::RParen)?;
+        }
 
-                self.expect(TokenType::EQUALS)?; 
-                let value = self.expr()?; 
-                self.expect(TokenType::SEM)?;
-                self.symbol_table.insert(name.clone().extract_str().unwrap(), VarInfo::new(value.get_type(),true));
+        Ok(Pattern::Variant { enum_name, variant, bindings, index : 0 })
+    }
+
+    // `match value { Pattern => expr, ... }`
+    fn parse_match_expr(&mut self) -> Result<Expr, Diagnostic> {
+        let start = self.expect(TokenType::MATCH)?.span;
+        let scrutinee = self.expr()?;
+        self.expect(TokenType::LCurly)?;
 
-                Box::new(VarDeclNode::new(name, value, var_type)) as Box<dyn Node>
+        let mut arms = vec![];
+        while self.current_tok.tok_type != TokenType::RCurly {
+            let pattern = self.parse_pattern()?;
+            self.expect(TokenType::ARROW)?;
+            arms.push((pattern, self.expr()?));
+
+            if self.current_tok.tok_type != TokenType::COMMA {
+                break;
             }
+            self.advance();
+        }
+        let rcurly = self.expect(TokenType::RCurly)?;
 
-            else if self.current_tok.tok_type == TokenType::FUN {
-                self.advance();
-                return Ok(vec![self.parse_main_func()]);
+        Ok(Expr::new(ExprKind::Match { scrutinee : Box::new(scrutinee), arms, id : 0 }, start.to(rcurly.span)))
+    }
+
+    // a match at the start of a statement is the statement form when its first
+    // arm is a block, and otherwise an expression (which only the REPL accepts
+    // on its own)
+    fn match_has_blocks(&mut self) -> bool {
+        let start = self.pos;
+        let found = (|| {
+            self.expect(TokenType::MATCH)?;
+            self.expr()?;
+            self.expect(TokenType::LCurly)?;
+            if self.current_tok.tok_type == TokenType::RCurly {
+                return Ok(true);
             }
+            self.parse_pattern()?;
+            self.expect(TokenType::ARROW)?;
+            Ok::<bool, Diagnostic>(self.current_tok.tok_type == TokenType::LCurly)
+        })();
+        self.pos = start;
+        self.current_tok = self.toks[start].clone();
+        // errors are reported again by the statement form
+        found.unwrap_or(true)
+    }
 
+    fn parse_type(&mut self) -> Result<Type, Diagnostic> {
+        if self.current_tok.tok_type == TokenType::LBracket {
+            self.advance();
+            let element = self.parse_type()?;
+            self.expect(TokenType::RBracket)?;
+            return Ok(Type::Array(Box::new(element)));
+        }
+        if self.current_tok.tok_type == TokenType::IDENTIFIER {
+            return Ok(Type::Named(self.ident()?.name));
+        }
 
-            else if self.current_tok.tok_type == TokenType::FOR {
-                self.advance();
+        let type_key = self.expect_vec(vec![
+            TokenType::IntKey,
+            TokenType::StringKey,
+            TokenType::FloatKey,
+            TokenType::BoolKey
+        ])?;
+
+        Ok(match type_key.tok_type {
+            TokenType::StringKey => Type::String,
+            TokenType::IntKey => Type::Int,
+            TokenType::FloatKey => Type::Float,
+            _ => Type::Bool,
+        })
+    }
+
+    fn parse_fun_header(&mut self) -> Result<(Ident, Vec<Param>, Type), Diagnostic> {
+        let name = self.ident()?;
+        self.expect(TokenType::LParen)?;
 
-                let start = self.expr()?;
+        let mut params : Vec<Param> = vec![];
+        if self.current_tok.tok_type != TokenType::RParen {
+            loop {
+                let param = self.ident()?;
                 self.expect(TokenType::Annotation)?;
-                let end = self.expr()?;
+                let ty = self.parse_type()?;
+                let c_name = param.name.clone();
+                params.push(Param { name : param, ty, c_name });
 
-                let (step, var);
-                if let Ok(_) = self.expect(TokenType::EQUALS) {
-                    step = Some(self.expr()?);
-                    var = self.expect(TokenType::IDENTIFIER)?;
-                } else {
-                    step = None;
-                    var = self.expect(TokenType::IDENTIFIER)?;
+                if self.current_tok.tok_type != TokenType::COMMA {
+                    break;
                 }
+                self.advance();
+            }
+        }
+        self.expect(TokenType::RParen)?;
 
-                self.symbol_table.insert(var.clone().extract_str().unwrap(), VarInfo::new(start.get_type(),false));
+        let mut return_type = Type::Void;
+        if self.current_tok.tok_type == TokenType::Annotation {
+            self.advance();
+            return_type = self.parse_type()?;
+        }
 
+        Ok((name, params, return_type))
+    }
 
-                self.expect(TokenType::LCurly)?;
+    fn parse_function(&mut self) -> Result<Function, Diagnostic> {
+        let start = self.current_tok.span;
+        let (name, params, return_type) = self.parse_fun_header()?;
 
-                let nodes = self.parse()?; 
+        self.expect(TokenType::LCurly)?;
+        let body = self.parse_block()?;
+        self.expect(TokenType::RCurly)?;
 
-                self.expect(TokenType::RCurly)?;
+        Ok(Function { name, params, return_type, body, span : start.to(self.prev_span()) })
+    }
 
-                self.symbol_table.remove(&var.extract_str().unwrap());
+    fn parse_struct(&mut self) -> Result<StructDef, Diagnostic> {
+        let start = self.expect(TokenType::STRUCT)?.span;
+        let name = self.ident()?;
+        self.expect(TokenType::LCurly)?;
 
-                let node = Box::new(ForLoopNode::new(start, end, var, nodes, step)) as Box<dyn Node>;
-                statements.push(node);
-                continue;
+        let mut fields = vec![];
+        while self.current_tok.tok_type != TokenType::RCurly {
+            let field = self.ident()?;
+            self.expect(TokenType::Annotation)?;
+            let ty = self.parse_type()?;
+            fields.push(FieldDef { name : field, ty });
+
+            if self.current_tok.tok_type != TokenType::COMMA {
+                break;
             }
+            self.advance();
+        }
+        let rcurly = self.expect(TokenType::RCurly)?;
 
+        if fields.is_empty() {
+            return Err(Diagnostic::error(diagnostic::EMPTY_STRUCT, format!("Struct '{}' has no fields", name.name))
+                .with_span(name.span));
+        }
+        Ok(StructDef { name, fields, span : start.to(rcurly.span) })
+    }
 
+    fn parse_enum(&mut self) -> Result<EnumDef, Diagnostic> {
+        let start = self.expect(TokenType::ENUM)?.span;
+        let name = self.ident()?;
+        self.expect(TokenType::LCurly)?;
 
-            else if self.current_tok.tok_type == TokenType::IF {
+        let mut variants = vec![];
+        while self.current_tok.tok_type != TokenType::RCurly {
+            let variant = self.ident()?;
+            let mut payload = vec![];
+            if self.current_tok.tok_type == TokenType::LParen {
                 self.advance();
-                let condition = self.expr()?;
-                self.expect(TokenType::LCurly)?;
-                let then_body = self.parse()?;
-                self.expect(TokenType::RCurly)?;
+                while self.current_tok.tok_type != TokenType::RParen {
+                    payload.push(self.parse_type()?);
+                    if self.current_tok.tok_type != TokenType::COMMA {
+                        break;
+                    }
+                    self.advance();
+                }
+                self.expect(TokenType::RParen)?;
+            }
+            variants.push(VariantDef { name : variant, payload });
+
+            if self.current_tok.tok_type != TokenType::COMMA {
+                break;
+            }
+            self.advance();
+        }
+        let rcurly = self.expect(TokenType::RCurly)?;
 
-                let mut elf_nodes = vec![];
-                let mut elf_bodies = vec![];
-                let mut else_body = None;
+        if variants.is_empty() {
+            return Err(Diagnostic::error(diagnostic::EMPTY_ENUM, format!("Enum '{}' has no variants", name.name))
+                .with_span(name.span));
+        }
+        Ok(EnumDef { name, variants, span : start.to(rcurly.span) })
+    }
 
-                while self.current_tok.tok_type == TokenType::ELF {
-                    self.advance(); 
-                    let elf_condition = self.expr()?;
-                    self.expect(TokenType::LCurly)?;
-                    let elf_body = self.parse()?; 
-                    self.expect(TokenType::RCurly)?;
+    fn parse_main_func(&mut self) -> Result<Function, Diagnostic> {
+        let main = self.expect(TokenType::MAIN)?;
+        self.expect(TokenType::LParen)?;
+        self.expect(TokenType::RParen)?;
 
-                    elf_nodes.push(elf_condition);
-                    elf_bodies.push(elf_body);
-                }
+        self.expect(TokenType::LCurly)?;
+        let body = self.parse_block()?;
+        self.expect(TokenType::RCurly)?;
 
-                if self.current_tok.tok_type == TokenType::ELSE {
-                    self.advance();
-                    self.expect(TokenType::LCurly)?;
-                    let body = self.parse()?;
-                    self.expect(TokenType::RCurly)?;
-                    else_body = Some(body);
-                }
+        let name = Ident { name : "main".to_string(), span : main.span };
+        Ok(Function { name, params : vec![], return_type : Type::Void, body, span : main.span.to(self.prev_span()) })
+    }
+
+    pub fn parse(&mut self) -> Result<Program, Diagnostic> {
+        let mut structs = vec![];
+        let mut enums = vec![];
+        let mut functions = vec![];
+        let mut main = None;
 
-                let node = Box::new(IfNode::new(condition, then_body, else_body, Some(elf_bodies), Some(elf_nodes))) as Box<dyn Node>;
-                statements.push(node);
+        while self.current_tok.tok_type != TokenType::EOF {
+            if self.current_tok.tok_type == TokenType::STRUCT {
+                structs.push(self.parse_struct()?);
                 continue;
             }
-
-            else if self.current_tok.tok_type == TokenType::WHILE {
-                self.advance();
-                let node = self.expr()?;
-                self.expect(TokenType::LCurly)?;
-                let body = self.parse()?;
-                self.expect(TokenType::RCurly)?;
-
-                let while_node= Box::new(WhileNode::new(node,body)) as Box<dyn Node>;
-                statements.push(while_node);
+            if self.current_tok.tok_type == TokenType::ENUM {
+                enums.push(self.parse_enum()?);
                 continue;
             }
+            self.expect(TokenType::FUN)?;
+            if self.current_tok.tok_type == TokenType::MAIN {
+                if main.is_some() {
+                    return Err(Diagnostic::error(diagnostic::DUPLICATE_DEFINITION, "Function 'main' is declared more than once".to_string()).with_span(self.current_tok.span));
+                }
+                main = Some(self.parse_main_func()?);
+            } else {
+                functions.push(self.parse_function()?);
+            }
+        }
 
-            else if self.current_tok.tok_type == TokenType::IDENTIFIER {
-                let name = self.current_tok.clone();
-                let name_str = name.extract_str().unwrap(); 
+        Ok(Program { structs, enums, functions, main })
+    }
 
-                if !self.symbol_table.contains_key(&name.extract_str().unwrap()) {
-                    return Err(format!("Error: variable '{}' used before declaration", name_str));
-                }
+    // one line (or several, for open blocks) typed into the REPL: declarations
+    // and statements in any order. The declarations come back as a Program
+    // without a main
+    pub fn parse_repl(&mut self) -> Result<(Program, Vec<Stmt>), Diagnostic> {
+        self.repl = true;
+        let mut structs = vec![];
+        let mut enums = vec![];
+        let mut functions = vec![];
+        let mut statements = vec![];
 
+        while self.current_tok.tok_type != TokenType::EOF {
+            if self.current_tok.tok_type == TokenType::STRUCT {
+                structs.push(self.parse_struct()?);
+            } else if self.current_tok.tok_type == TokenType::ENUM {
+                enums.push(self.parse_enum()?);
+            } else if self.current_tok.tok_type == TokenType::FUN {
                 self.advance();
-                self.expect(TokenType::EQUALS)?;
-                let value = self.expr()?;
-                self.expect(TokenType::SEM)?;
-
-                let expected_type = self.symbol_table.get(&name.extract_str().unwrap()).unwrap(); 
-                let value_type = value.get_type();
+                functions.push(self.parse_function()?);
+            } else {
+                statements.push(self.parse_statement()?);
+            }
+        }
 
-                if expected_type.tok_type != value_type {
-                        return Err(format!(
-                            "Type Error: vfnariable '{}' expects type '{:?}', but got '{:?}'",
-                            name_str, expected_type, value_type
-                        ));
-                    }
+        Ok((Program { structs, enums, functions, main : None }, statements))
+    }
 
-                    Box::new(VarAssignNode::new(name.clone(), value)) as Box<dyn Node>
-                }
+    fn end_statement(&mut self) -> Result<(), Diagnostic> {
+        if self.repl && self.current_tok.tok_type == TokenType::EOF {
+            return Ok(());
+        }
+        self.expect(TokenType::SEM)?;
+        Ok(())
+    }
 
-                else {
-                    let expr = self.expr()?;
-                    if expr.is_pure_value() {
-                        return Err(format!(
-                            "Unexpected standalone value or expression: '{}'",
-                            expr.generate()
-                        ));
-                    }
-                    expr
-                };
+    fn parse_block(&mut self) -> Result<Vec<Stmt>, Diagnostic> {
+        let mut statements = vec![];
 
-                statements.push(stmt);
-            }
+        while self.current_tok.tok_type != TokenType::EOF  && self.current_tok.tok_type != TokenType::RCurly {
+            statements.push(self.parse_statement()?);
+        }
 
         Ok(statements)
     }
-}
 
+    fn parse_statement(&mut self) -> Result<Stmt, Diagnostic> {
+        let keyword_span = self.current_tok.span;
+        let print = match self.current_tok.tok_type {
+            TokenType::PRINT => Some(("print", Stream::Stdout, false)),
+            TokenType::PRINTLN => Some(("println", Stream::Stdout, true)),
+            TokenType::EPRINT => Some(("eprint", Stream::Stderr, false)),
+            TokenType::EPRINTLN => Some(("eprintln", Stream::Stderr, true)),
+            _ => None,
+        };
+        let kind = if let Some((keyword, stream, newline)) = print {
+            self.advance(); 
+            let lparen = self.expect(TokenType::LParen)?;
+            // `println();` just ends the line
+            let mut args = if newline && self.current_tok.tok_type == TokenType::RParen {
+                vec![Expr::new(ExprKind::Str(String::new()), lparen.span)]
+            } else {
+                self.parse_print_ags()?
+            };
+            self.expect(TokenType::RParen)?;
+            self.end_statement()?;
+
+            let format = match &args[0].kind {
+                ExprKind::Str(format) => format.clone(),
+                _ => return Err(Diagnostic::error(diagnostic::PRINT_FORMAT, format!("First argument to {} must be a string literal", keyword))
+                    .with_span(args[0].span)),
+            };
+            let format_span = args.remove(0).span;
+            StmtKind::Print { format, format_span, args, stream, newline }
+        }
 
-const TYPE_LIB : &str = r#"#include <stdio.h>
-#include <gc.h>
-#include <string.h>
+        else if self.current_tok.tok_type == TokenType::LET {
+            self.advance(); 
+            let name = self.ident()?;
 
-"#;
+            let mut declared_type = None;
 
-pub fn run()  {
-    let path = "test/1.fun";
-    let mut input = String::new();
+            if self.expect(TokenType::Annotation).is_ok() {
+                declared_type = Some(self.parse_type()?);
+            }
 
-    match fs::read_to_string(path) {
-        Ok(content) => {
-            input = content;
+            self.expect(TokenType::EQUALS)?; 
+            let value = self.expr()?; 
+            self.end_statement()?;
+
+            let c_name = name.name.clone();
+            StmtKind::Let { name, declared_type, value, c_name, escapes : None }
         }
-        
-        Err(error) => {
-            eprintln!("Error reading file: {}", error);
+
+        else if self.current_tok.tok_type == TokenType::FUN {
+            return Err(Diagnostic::error(diagnostic::MISPLACED_DECLARATION, "Functions can only be declared at the top level".to_string()).with_span(keyword_span));
         }
-    }
 
-    let mut scanner = Scanner::new(input);
-    scanner.tokenize();
+        else if self.current_tok.tok_type == TokenType::STRUCT {
+            return Err(Diagnostic::error(diagnostic::MISPLACED_DECLARATION, "Structs can only be declared at the top level".to_string()).with_span(keyword_span));
+        }
 
-    let mut out = String::new();
-    let mut parser = Parser::new(scanner.toks);
-    out.push_str(TYPE_LIB);
+        else if self.current_tok.tok_type == TokenType::ENUM {
+            return Err(Diagnostic::error(diagnostic::MISPLACED_DECLARATION, "Enums can only be declared at the top level".to_string()).with_span(keyword_span));
+        }
+
+        else if self.current_tok.tok_type == TokenType::MATCH && self.match_has_blocks() {
+            self.advance();
+            let scrutinee = self.expr()?;
+            self.expect(TokenType::LCurly)?;
 
+            let mut arms = vec![];
+            while self.current_tok.tok_type != TokenType::RCurly {
+                let pattern = self.parse_pattern()?;
+                self.expect(TokenType::ARROW)?;
+                self.expect(TokenType::LCurly)?;
+                let body = self.parse_block()?;
+                self.expect(TokenType::RCurly)?;
+                arms.push((pattern, body));
 
-    match parser.parse() {
-        Ok(res) => {
-            for r in res.into_iter() {
-                out.push_str(&r.generate());
+                if self.current_tok.tok_type == TokenType::COMMA {
+                    self.advance();
+                }
             }
+            self.expect(TokenType::RCurly)?;
+
+            StmtKind::Match { scrutinee, arms }
         }
-        Err(e) => {
-            eprintln!("Error: {}", e);
+
+        else if self.current_tok.tok_type == TokenType::RETURN {
+            self.advance();
+            let value = if self.current_tok.tok_type == TokenType::SEM || (self.repl && self.current_tok.tok_type == TokenType::EOF) {
+                None
+            } else {
+                Some(self.expr()?)
+            };
+            self.end_statement()?;
+
+            StmtKind::Return { value }
         }
-    }
 
-    let file = File::create("out/output.c");
-    let _ = file.unwrap().write_all(out.as_bytes());
-    println!("{}",out);
+        else if self.current_tok.tok_type == TokenType::FOR {
+            self.advance();
+
+            let start = self.expr()?;
+            self.expect(TokenType::Annotation)?;
+            let end = self.expr()?;
+
+            let mut step = None;
+            if self.expect(TokenType::EQUALS).is_ok() {
+                step = Some(self.expr()?);
+            }
+            let var = self.ident()?;
+
+            self.expect(TokenType::LCurly)?;
+            let body = self.parse_block()?; 
+            self.expect(TokenType::RCurly)?;
+
+            let c_var = var.name.clone();
+            StmtKind::For { var, c_var, start, end, step, body }
+        }
+
+        else if self.current_tok.tok_type == TokenType::IF {
+            self.advance();
+            let condition = self.expr()?;
+            self.expect(TokenType::LCurly)?;
+            let then_body = self.parse_block()?;
+            self.expect(TokenType::RCurly)?;
+
+            let mut branches = vec![(condition, then_body)];
+            let mut else_body = None;
 
+            while self.current_tok.tok_type == TokenType::ELF {
+                self.advance(); 
+                let elf_condition = self.expr()?;
+                self.expect(TokenType::LCurly)?;
+                let elf_body = self.parse_block()?; 
+                self.expect(TokenType::RCurly)?;
+
+                branches.push((elf_condition, elf_body));
+            }
+
+            if self.current_tok.tok_type == TokenType::ELSE {
+                self.advance();
+                self.expect(TokenType::LCurly)?;
+                let body = self.parse_block()?;
+                self.expect(TokenType::RCurly)?;
+                else_body = Some(body);
+            }
+
+            StmtKind::If { branches, else_body }
+        }
+
+        else if self.current_tok.tok_type == TokenType::WHILE {
+            self.advance();
+            let cond = self.expr()?;
+            self.expect(TokenType::LCurly)?;
+            let body = self.parse_block()?;
+            self.expect(TokenType::RCurly)?;
 
+            StmtKind::While { cond, body }
+        }
+
+        else if self.current_tok.tok_type == TokenType::IDENTIFIER && self.peek_type() == TokenType::EQUALS {
+            let name = self.ident()?;
+
+            self.expect(TokenType::EQUALS)?;
+            let value = self.expr()?;
+            self.end_statement()?;
+
+            let c_name = name.name.clone();
+            StmtKind::Assign { name, value, c_name }
+        }
+
+        else {
+            let expr = self.expr()?;
+            if self.current_tok.tok_type == TokenType::EQUALS && matches!(expr.kind, ExprKind::Index { .. } | ExprKind::Field { .. }) {
+                self.advance();
+                let value = self.expr()?;
+                self.end_statement()?;
+                return Ok(Stmt::new(StmtKind::Set { target : expr, value }, keyword_span.to(self.prev_span())));
+            }
+            if !self.repl && !matches!(expr.kind, ExprKind::Call { .. }) {
+                return Err(Diagnostic::error(diagnostic::STANDALONE_VALUE, "Unexpected standalone value or expression".to_string())
+                    .with_span(expr.span));
+            }
+            self.end_statement()?;
+            StmtKind::Expr(expr)
+        };
+
+        Ok(Stmt::new(kind, keyword_span.to(self.prev_span())))
+    }
 }
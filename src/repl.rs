@@ -0,0 +1,170 @@
+// `funlang repl`: reads statements, declarations and bare expressions
+// line by line and runs them with the interpreter. Variables and functions live
+// for the whole session.
+use std::io::{self,IsTerminal,Write};
+
+use crate::checker::Checker;
+use crate::diagnostic::{self,Diagnostic};
+use crate::interp::{self,Env,Interpreter,Value};
+use crate::lexer::{Scanner,Token,TokenType};
+use crate::nodes::*;
+use crate::paser::Parser;
+use crate::source::SourceFile;
+
+const PROMPT : &str = ">>> ";
+const CONTINUE_PROMPT : &str = "... ";
+
+struct Session {
+    checker : Checker,
+    interp : Interpreter,
+    env : Env,
+    // every input so far, indexed by Span::file. Functions from earlier inputs
+    // report runtime errors in their own input
+    sources : Vec<SourceFile>,
+}
+
+pub fn run() -> i32 {
+    let result = interp::with_big_stack(|| {
+        let mut session = Session { checker : Checker::new(), interp : Interpreter::new(), env : Env::new(), sources : vec![] };
+        session.read_loop();
+        Ok(0)
+    });
+    match result {
+        Ok(code) => code,
+        Err(d) => {
+            eprint!("{}", d.render(None));
+            1
+        }
+    }
+}
+
+// true while the input so far can't be complete: a `{` or `(` is still open,
+// or a block comment hasn't ended
+fn unfinished(input : &str) -> bool {
+    let mut scanner = Scanner::new(input.to_string());
+    match scanner.tokenize() {
+        Ok(()) => depth(&scanner.toks) > 0,
+        Err(d) => d.code == diagnostic::UNTERMINATED_COMMENT,
+    }
+}
+
+fn depth(toks : &[Token]) -> i32 {
+    toks.iter().map(|tok| match tok.tok_type {
+        TokenType::LCurly | TokenType::LParen => 1,
+        TokenType::RCurly | TokenType::RParen => -1,
+        _ => 0,
+    }).sum()
+}
+
+// like Value::to_c_string, but strings are quoted so "" and " " are visible
+fn show(value : &Value) -> String {
+    match value {
+        Value::Str(s) => format!("{:?}", s),
+        value => value.to_c_string(),
+    }
+}
+
+impl Session {
+    fn read_loop(&mut self) {
+        let interactive = io::stdin().is_terminal();
+        if interactive {
+            println!("FunLang REPL. Type :quit or press Ctrl-D to leave.");
+        }
+
+        let mut input = String::new();
+        loop {
+            if interactive {
+                print!("{}", if input.is_empty() { PROMPT } else { CONTINUE_PROMPT });
+                let _ = io::stdout().flush();
+            }
+            // stdin is only locked while reading, so read_line() in the
+            // program being run can read the next lines too
+            let mut line = String::new();
+            if !matches!(io::stdin().read_line(&mut line), Ok(n) if n > 0) {
+                break;
+            }
+            let line = line.trim_end_matches(['\n', '\r']);
+            if input.is_empty() && matches!(line.trim(), ":quit" | ":q") {
+                break;
+            }
+
+            input.push_str(line);
+            input.push('\n');
+            if input.trim().is_empty() {
+                input.clear();
+                continue;
+            }
+            if unfinished(&input) {
+                continue;
+            }
+
+            self.sources.push(SourceFile::new("<repl>", std::mem::take(&mut input)));
+            if let Err(diagnostics) = self.eval(self.sources.len() - 1) {
+                for d in &diagnostics {
+                    let source = d.span.and_then(|span| self.sources.get(span.file));
+                    eprint!("{}", d.render(source));
+                }
+            }
+        }
+        if interactive {
+            println!();
+        }
+    }
+
+    // checks one input as a whole and then runs it. Nothing is kept from an
+    // input that fails to check, and variables are put back as they were if it
+    // fails at runtime
+    fn eval(&mut self, file : usize) -> Result<(), Vec<Diagnostic>> {
+        let mut scanner = Scanner::new(self.sources[file].text.clone()).in_file(file);
+        scanner.tokenize()?;
+        let (mut program, mut stmts) = Parser::new(scanner.toks).parse_repl()?;
+
+        let before = self.checker.clone();
+        let mut diagnostics = vec![];
+        if let Err(errors) = self.checker.check_declarations(&mut program) {
+            diagnostics.extend(errors);
+        }
+        let declared = self.checker.clone();
+        if let Err(errors) = self.checker.check_stmts(&mut stmts) {
+            diagnostics.extend(errors);
+        }
+        if !diagnostics.is_empty() {
+            self.checker = before;
+            return Err(diagnostics);
+        }
+
+        for def in program.structs {
+            self.interp.define_struct(def);
+        }
+        for def in program.enums {
+            self.interp.define_enum(def);
+        }
+        for function in program.functions {
+            self.interp.define(function);
+        }
+        let env = self.env.clone();
+        let result = self.exec(&stmts);
+        self.interp.flush();
+        if let Err(d) = result {
+            self.checker = declared;
+            self.env = env;
+            return Err(vec![d]);
+        }
+        Ok(())
+    }
+
+    fn exec(&mut self, stmts : &[Stmt]) -> Result<(), Diagnostic> {
+        for stmt in stmts {
+            if let StmtKind::Expr(e) = &stmt.kind && e.ty != Type::Void {
+                let value = self.interp.eval(e, &mut self.env)?;
+                self.interp.flush();
+                println!("{} : {}", show(&value), e.ty);
+                continue;
+            }
+            if let interp::Flow::Return(_) = self.interp.exec(stmt, &mut self.env)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,665 @@
+// The C that goes in front of every generated program. Generated code allocates
+// with fl_alloc and calls fl_init at the start of main; the memory mode decides
+// what those are. Everything else is a table of definitions that lists what
+// each one needs, and a program only gets the definitions it uses.
+use std::collections::HashSet;
+
+use crate::codegen;
+
+const HEADERS : &str = r#"#include <stdio.h>
+#include <stdlib.h>
+#include <stdarg.h>
+#include <stddef.h>
+#include <stdbool.h>
+#include <string.h>
+#include <errno.h>
+
+"#;
+
+// a definition in the runtime: the name generated code and other entries know
+// it by, the entries its code uses, which come before it in its table, and the
+// code itself
+struct Entry {
+    name : &'static str,
+    needs : &'static [&'static str],
+    code : &'static str,
+}
+
+// with gc.h, which prelude includes
+const GC : &[Entry] = &[
+    Entry { name : "fl_alloc", needs : &[], code : "#define fl_alloc(n) GC_MALLOC(n)" },
+    Entry { name : "fl_init", needs : &[], code : "#define fl_init() GC_INIT()" },
+];
+
+// for the allocators that get their memory from calloc
+const OUT_OF_MEMORY : Entry = Entry { name : "fl_out_of_memory", needs : &[], code : r#"static void fl_out_of_memory(void) {
+    fputs("error: out of memory\n", stderr);
+    exit(1);
+}"# };
+
+// bump allocation out of big chunks that are all freed at exit
+const ARENA : &[Entry] = &[
+    OUT_OF_MEMORY,
+    Entry { name : "FL_CHUNK_SIZE", needs : &[], code : r#"#define FL_CHUNK_SIZE (1 << 20)"# },
+    Entry { name : "fl_chunk", needs : &[], code : r#"typedef struct fl_chunk {
+    struct fl_chunk* next;
+    size_t used;
+    size_t size;
+    max_align_t data[];
+} fl_chunk;"# },
+    Entry { name : "fl_arena", needs : &["fl_chunk"], code : r#"static fl_chunk* fl_arena = NULL;"# },
+    Entry { name : "fl_arena_free", needs : &["fl_chunk", "fl_arena"], code : r#"static void fl_arena_free(void) {
+    while (fl_arena != NULL) {
+        fl_chunk* next = fl_arena->next;
+        free(fl_arena);
+        fl_arena = next;
+    }
+}"# },
+    Entry { name : "fl_alloc", needs : &["fl_out_of_memory", "FL_CHUNK_SIZE", "fl_chunk", "fl_arena"], code : r#"static void* fl_alloc(size_t n) {
+    n = (n + sizeof(max_align_t) - 1) / sizeof(max_align_t) * sizeof(max_align_t);
+    if (fl_arena == NULL || fl_arena->size - fl_arena->used < n) {
+        size_t size = n > FL_CHUNK_SIZE ? n : FL_CHUNK_SIZE;
+        fl_chunk* chunk = calloc(1, sizeof(fl_chunk) + size);
+        if (chunk == NULL) {
+            fl_out_of_memory();
+        }
+        chunk->next = fl_arena;
+        chunk->used = 0;
+        chunk->size = size;
+        fl_arena = chunk;
+    }
+    void* p = (char*) fl_arena->data + fl_arena->used;
+    fl_arena->used += n;
+    return p;
+}"# },
+    Entry { name : "fl_init", needs : &["fl_arena_free"], code : r#"static void fl_init(void) {
+    atexit(fl_arena_free);
+}"# },
+];
+
+// nothing is freed; the OS takes it all back at exit
+const MALLOC : &[Entry] = &[
+    OUT_OF_MEMORY,
+    Entry { name : "fl_alloc", needs : &["fl_out_of_memory"], code : r#"static void* fl_alloc(size_t n) {
+    void* p = calloc(1, n);
+    if (p == NULL) {
+        fl_out_of_memory();
+    }
+    return p;
+}"# },
+    Entry { name : "fl_init", needs : &[], code : r#"static void fl_init(void) {}"# },
+];
+
+// what comes after the allocator, in the order it's emitted
+const RUNTIME : &[Entry] = &[
+    // reports like the interpreter does, pointing at the FunLang source
+    // (FL_SOURCE is defined by prelude)
+    Entry { name : "fl_runtime_error", needs : &[], code : r#"static void fl_runtime_error(int line, int col, const char* format, ...) {
+    fflush(stdout);
+    fputs("error[E0500]: ", stderr);
+    va_list args;
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fprintf(stderr, "\n --> %s:%d:%d\n", FL_SOURCE, line, col);
+    exit(1);
+}"# },
+
+    // int / and %, which C would trap on for a zero divisor and for INT_MIN / -1.
+    // The latter wraps around, like in the interpreter
+    Entry { name : "fl_div", needs : &["fl_runtime_error"], code : r#"static int fl_div(int a, int b, int line, int col) {
+    if (b == 0) {
+        fl_runtime_error(line, col, "Division by zero");
+    }
+    if (b == -1) {
+        return (int) (0u - (unsigned) a);
+    }
+    return a / b;
+}"# },
+    Entry { name : "fl_mod", needs : &["fl_runtime_error"], code : r#"static int fl_mod(int a, int b, int line, int col) {
+    if (b == 0) {
+        fl_runtime_error(line, col, "Division by zero");
+    }
+    if (b == -1) {
+        return 0;
+    }
+    return a % b;
+}"# },
+
+    // every FunLang array is a pointer to one of these, so arrays are shared when
+    // assigned or passed, like in the interpreter
+    Entry { name : "fl_array", needs : &[], code : r#"typedef struct fl_array {
+    int len;
+    int cap;
+    size_t elem_size;
+    char* data;
+} fl_array;"# },
+    // fills in a header that lives wherever the caller keeps it
+    Entry { name : "fl_array_init", needs : &["fl_alloc", "fl_array"], code : r#"static fl_array* fl_array_init(fl_array* a, size_t elem_size, int len, const void* items) {
+    a->len = len;
+    a->cap = len;
+    a->elem_size = elem_size;
+    a->data = fl_alloc(elem_size * (len > 0 ? len : 1));
+    if (len > 0) {
+        memcpy(a->data, items, elem_size * len);
+    }
+    return a;
+}"# },
+    Entry { name : "fl_array_new", needs : &["fl_alloc", "fl_array", "fl_array_init"], code : r#"static fl_array* fl_array_new(size_t elem_size, int len, const void* items) {
+    return fl_array_init(fl_alloc(sizeof(fl_array)), elem_size, len, items);
+}"# },
+    Entry { name : "fl_array_at", needs : &["fl_runtime_error", "fl_array"], code : r#"static void* fl_array_at(fl_array* a, int i, int line, int col) {
+    if (i < 0 || i >= a->len) {
+        fl_runtime_error(line, col, "Index %d is out of bounds for an array of length %d", i, a->len);
+    }
+    return a->data + (size_t) i * a->elem_size;
+}"# },
+    Entry { name : "fl_array_set", needs : &["fl_array", "fl_array_at"], code : r#"static void fl_array_set(fl_array* a, int i, const void* value, int line, int col) {
+    memcpy(fl_array_at(a, i, line, col), value, a->elem_size);
+}"# },
+    Entry { name : "fl_array_push", needs : &["fl_alloc", "fl_array"], code : r#"static void fl_array_push(fl_array* a, const void* value) {
+    if (a->len == a->cap) {
+        int cap = a->cap < 4 ? 4 : a->cap * 2;
+        char* data = fl_alloc(a->elem_size * cap);
+        memcpy(data, a->data, a->elem_size * a->len);
+        a->data = data;
+        a->cap = cap;
+    }
+    memcpy(a->data + (size_t) a->len * a->elem_size, value, a->elem_size);
+    a->len++;
+}"# },
+    // the element stays in the buffer until the next push, so the caller reads it
+    // right away
+    Entry { name : "fl_array_pop", needs : &["fl_runtime_error", "fl_array"], code : r#"static void* fl_array_pop(fl_array* a, int line, int col) {
+    if (a->len == 0) {
+        fl_runtime_error(line, col, "Can't pop from an empty array");
+    }
+    a->len--;
+    return a->data + (size_t) a->len * a->elem_size;
+}"# },
+
+    // strings are immutable, so these always build a new one. Lengths and
+    // positions count bytes, like strlen
+    Entry { name : "fl_concat", needs : &["fl_alloc"], code : r#"static char* fl_concat(const char* a, const char* b) {
+    size_t len_a = strlen(a);
+    size_t len_b = strlen(b);
+    char* s = fl_alloc(len_a + len_b + 1);
+    memcpy(s, a, len_a);
+    memcpy(s + len_a, b, len_b + 1);
+    return s;
+}"# },
+    Entry { name : "fl_copy", needs : &["fl_alloc"], code : r#"static char* fl_copy(const char* s, size_t len) {
+    char* out = fl_alloc(len + 1);
+    memcpy(out, s, len);
+    out[len] = '\0';
+    return out;
+}"# },
+    // the second and later bytes of a UTF-8 character
+    Entry { name : "fl_is_continuation", needs : &[], code : r#"static bool fl_is_continuation(char c) {
+    return ((unsigned char) c & 0xC0) == 0x80;
+}"# },
+    Entry { name : "fl_is_space", needs : &[], code : r#"static bool fl_is_space(char c) {
+    return c == ' ' || (c >= '\t' && c <= '\r');
+}"# },
+    Entry { name : "fl_substr", needs : &["fl_runtime_error", "fl_copy", "fl_is_continuation"], code : r#"static char* fl_substr(const char* s, int start, int count, int line, int col) {
+    int len = (int) strlen(s);
+    if (start < 0 || count < 0 || start > len - count) {
+        fl_runtime_error(line, col, "substr(%d, %d) is out of range for a string of length %d", start, count, len);
+    }
+    if (fl_is_continuation(s[start]) || fl_is_continuation(s[start + count])) {
+        fl_runtime_error(line, col, "substr(%d, %d) would split a UTF-8 character", start, count);
+    }
+    return fl_copy(s + start, count);
+}"# },
+    Entry { name : "fl_find", needs : &[], code : r#"static int fl_find(const char* s, const char* part) {
+    const char* found = strstr(s, part);
+    return found == NULL ? -1 : (int) (found - s);
+}"# },
+    // an empty `from` leaves the string as it is
+    Entry { name : "fl_replace", needs : &["fl_alloc", "fl_copy"], code : r#"static char* fl_replace(const char* s, const char* from, const char* to) {
+    size_t len_from = strlen(from);
+    size_t len_to = strlen(to);
+    if (len_from == 0) {
+        return fl_copy(s, strlen(s));
+    }
+    size_t count = 0;
+    for (const char* p = strstr(s, from); p != NULL; p = strstr(p + len_from, from)) {
+        count++;
+    }
+    char* out = fl_alloc(strlen(s) - count * len_from + count * len_to + 1);
+    char* w = out;
+    const char* rest = s;
+    for (const char* p = strstr(rest, from); p != NULL; p = strstr(rest, from)) {
+        memcpy(w, rest, p - rest);
+        w += p - rest;
+        memcpy(w, to, len_to);
+        w += len_to;
+        rest = p + len_from;
+    }
+    strcpy(w, rest);
+    return out;
+}"# },
+    Entry { name : "fl_split", needs : &["fl_runtime_error", "fl_array", "fl_array_new", "fl_array_push", "fl_copy"], code : r#"static fl_array* fl_split(const char* s, const char* sep, int line, int col) {
+    size_t len_sep = strlen(sep);
+    if (len_sep == 0) {
+        fl_runtime_error(line, col, "Can't split on an empty separator");
+    }
+    fl_array* parts = fl_array_new(sizeof(char*), 0, NULL);
+    const char* rest = s;
+    for (const char* p = strstr(rest, sep); p != NULL; p = strstr(rest, sep)) {
+        char* part = fl_copy(rest, p - rest);
+        fl_array_push(parts, &part);
+        rest = p + len_sep;
+    }
+    char* last = fl_copy(rest, strlen(rest));
+    fl_array_push(parts, &last);
+    return parts;
+}"# },
+    Entry { name : "fl_trim", needs : &["fl_copy", "fl_is_space"], code : r#"static char* fl_trim(const char* s) {
+    while (fl_is_space(*s)) {
+        s++;
+    }
+    size_t len = strlen(s);
+    while (len > 0 && fl_is_space(s[len - 1])) {
+        len--;
+    }
+    return fl_copy(s, len);
+}"# },
+    // ASCII letters only, whatever the C locale is
+    Entry { name : "fl_to_upper", needs : &["fl_copy"], code : r#"static char* fl_to_upper(const char* s) {
+    char* out = fl_copy(s, strlen(s));
+    for (char* p = out; *p != '\0'; p++) {
+        if (*p >= 'a' && *p <= 'z') {
+            *p = *p - 'a' + 'A';
+        }
+    }
+    return out;
+}"# },
+    Entry { name : "fl_to_lower", needs : &["fl_copy"], code : r#"static char* fl_to_lower(const char* s) {
+    char* out = fl_copy(s, strlen(s));
+    for (char* p = out; *p != '\0'; p++) {
+        if (*p >= 'A' && *p <= 'Z') {
+            *p = *p - 'A' + 'a';
+        }
+    }
+    return out;
+}"# },
+    Entry { name : "fl_starts_with", needs : &[], code : r#"static bool fl_starts_with(const char* s, const char* prefix) {
+    return strncmp(s, prefix, strlen(prefix)) == 0;
+}"# },
+    Entry { name : "fl_ends_with", needs : &[], code : r#"static bool fl_ends_with(const char* s, const char* suffix) {
+    size_t len = strlen(s);
+    size_t len_suffix = strlen(suffix);
+    return len >= len_suffix && strcmp(s + len - len_suffix, suffix) == 0;
+}"# },
+
+    // where the printers of arrays, structs and enums write: stdout, stderr, or the
+    // string format() is building. printf has no conversion for {:b}, so fl_binary
+    // writes it into `buf`, which needs room for 33 chars
+    Entry { name : "fl_out", needs : &[], code : r#"typedef struct fl_out {
+    int stream; // 1 for stdout, 2 for stderr, 0 for data
+    char* data;
+    size_t len;
+    size_t cap;
+} fl_out;"# },
+    Entry { name : "fl_stdout", needs : &["fl_out"], code : r#"static fl_out fl_stdout = {1, NULL, 0, 0};"# },
+    Entry { name : "fl_stderr", needs : &["fl_out"], code : r#"static fl_out fl_stderr = {2, NULL, 0, 0};"# },
+    Entry { name : "fl_buffer", needs : &["fl_alloc", "fl_out"], code : r#"static fl_out* fl_buffer(void) {
+    fl_out* out = fl_alloc(sizeof(fl_out));
+    out->stream = 0;
+    out->len = 0;
+    out->cap = 16;
+    out->data = fl_alloc(out->cap);
+    out->data[0] = '\0';
+    return out;
+}"# },
+    Entry { name : "fl_write", needs : &["fl_alloc", "fl_out"], code : r#"static fl_out* fl_write(fl_out* out, const char* format, ...) {
+    va_list args;
+    va_start(args, format);
+    if (out->stream != 0) {
+        vfprintf(out->stream == 1 ? stdout : stderr, format, args);
+        va_end(args);
+        return out;
+    }
+    va_list measure;
+    va_copy(measure, args);
+    size_t n = (size_t) vsnprintf(NULL, 0, format, measure);
+    va_end(measure);
+    if (out->len + n + 1 > out->cap) {
+        size_t cap = out->cap * 2 > out->len + n + 1 ? out->cap * 2 : out->len + n + 1;
+        char* data = fl_alloc(cap);
+        memcpy(data, out->data, out->len + 1);
+        out->data = data;
+        out->cap = cap;
+    }
+    vsnprintf(out->data + out->len, n + 1, format, args);
+    out->len += n;
+    va_end(args);
+    return out;
+}"# },
+    Entry { name : "fl_done", needs : &["fl_out"], code : r#"static char* fl_done(fl_out* out) {
+    return out->data;
+}"# },
+    Entry { name : "fl_binary", needs : &[], code : r#"static char* fl_binary(char* buf, int value) {
+    unsigned bits = (unsigned) value;
+    char digits[32];
+    int n = 0;
+    do {
+        digits[n++] = (char) ('0' + (bits & 1));
+        bits >>= 1;
+    } while (bits != 0);
+    for (int i = 0; i < n; i++) {
+        buf[i] = digits[n - 1 - i];
+    }
+    buf[n] = '\0';
+    return buf;
+}"# },
+
+    // read_line gives lines without their line break; read_int and read_float
+    // read whitespace-separated words, like scanf. Each optional type is laid out
+    // like the enum `{ Some(T), None }` so match can switch on it
+    Entry { name : "fl_opt_string", needs : &[], code : r#"typedef struct fl_opt_string { int tag; union { struct { char* _0; } Some; } as; } fl_opt_string;"# },
+    Entry { name : "fl_opt_int", needs : &[], code : r#"typedef struct fl_opt_int { int tag; union { struct { int _0; } Some; } as; } fl_opt_int;"# },
+    Entry { name : "fl_opt_float", needs : &[], code : r#"typedef struct fl_opt_float { int tag; union { struct { float _0; } Some; } as; } fl_opt_float;"# },
+    Entry { name : "fl_push_char", needs : &["fl_alloc"], code : r#"static void fl_push_char(char** buf, size_t* len, size_t* cap, int c) {
+    if (*len + 1 == *cap) {
+        char* grown = fl_alloc(*cap * 2);
+        memcpy(grown, *buf, *len);
+        *buf = grown;
+        *cap *= 2;
+    }
+    (*buf)[(*len)++] = (char) c;
+}"# },
+    // NULL at the end of the input
+    Entry { name : "fl_next_line", needs : &["fl_alloc", "fl_push_char"], code : r#"static char* fl_next_line(void) {
+    fflush(stdout);
+    size_t len = 0;
+    size_t cap = 64;
+    char* buf = fl_alloc(cap);
+    int c;
+    while ((c = getchar()) != EOF && c != '\n') {
+        fl_push_char(&buf, &len, &cap, c);
+    }
+    if (c == EOF && len == 0) {
+        return NULL;
+    }
+    if (len > 0 && buf[len - 1] == '\r') {
+        len--;
+    }
+    buf[len] = '\0';
+    return buf;
+}"# },
+    // the whitespace after the word is left for the next read. NULL at the end of
+    // the input
+    Entry { name : "fl_next_word", needs : &["fl_alloc", "fl_is_space", "fl_push_char"], code : r#"static char* fl_next_word(void) {
+    fflush(stdout);
+    int c;
+    while ((c = getchar()) != EOF && fl_is_space((char) c)) {
+    }
+    if (c == EOF) {
+        return NULL;
+    }
+    size_t len = 0;
+    size_t cap = 16;
+    char* buf = fl_alloc(cap);
+    while (c != EOF && !fl_is_space((char) c)) {
+        fl_push_char(&buf, &len, &cap, c);
+        c = getchar();
+    }
+    if (c != EOF) {
+        ungetc(c, stdin);
+    }
+    buf[len] = '\0';
+    return buf;
+}"# },
+    Entry { name : "fl_parse_int", needs : &["fl_runtime_error"], code : r#"static int fl_parse_int(const char* word, int line, int col) {
+    const char* digits = word[0] == '+' || word[0] == '-' ? word + 1 : word;
+    bool valid = *digits != '\0';
+    for (const char* p = digits; *p != '\0'; p++) {
+        valid = valid && *p >= '0' && *p <= '9';
+    }
+    long long value = 0;
+    for (const char* p = digits; valid && *p != '\0'; p++) {
+        value = value * 10 + (*p - '0');
+        valid = word[0] == '-' ? -value >= -2147483647LL - 1 : value <= 2147483647LL;
+    }
+    if (!valid) {
+        fl_runtime_error(line, col, "read_int() expected an int, but got '%s'", word);
+    }
+    return (int) (word[0] == '-' ? -value : value);
+}"# },
+    // digits with an optional sign, decimal point and exponent
+    Entry { name : "fl_parse_float", needs : &["fl_runtime_error"], code : r#"static float fl_parse_float(const char* word, int line, int col) {
+    const char* p = word[0] == '+' || word[0] == '-' ? word + 1 : word;
+    int digits = 0;
+    for (; *p >= '0' && *p <= '9'; p++) {
+        digits++;
+    }
+    if (*p == '.') {
+        for (p++; *p >= '0' && *p <= '9'; p++) {
+            digits++;
+        }
+    }
+    bool valid = digits > 0;
+    if (valid && (*p == 'e' || *p == 'E')) {
+        p++;
+        if (*p == '+' || *p == '-') {
+            p++;
+        }
+        valid = *p >= '0' && *p <= '9';
+        while (*p >= '0' && *p <= '9') {
+            p++;
+        }
+    }
+    if (!valid || *p != '\0') {
+        fl_runtime_error(line, col, "read_float() expected a float, but got '%s'", word);
+    }
+    return strtof(word, NULL);
+}"# },
+    Entry { name : "fl_read_line", needs : &["fl_runtime_error", "fl_next_line"], code : r#"static char* fl_read_line(int line, int col) {
+    char* s = fl_next_line();
+    if (s == NULL) {
+        fl_runtime_error(line, col, "read_line() reached the end of the input");
+    }
+    return s;
+}"# },
+    Entry { name : "fl_read_int", needs : &["fl_runtime_error", "fl_next_word", "fl_parse_int"], code : r#"static int fl_read_int(int line, int col) {
+    char* word = fl_next_word();
+    if (word == NULL) {
+        fl_runtime_error(line, col, "read_int() reached the end of the input");
+    }
+    return fl_parse_int(word, line, col);
+}"# },
+    Entry { name : "fl_read_float", needs : &["fl_runtime_error", "fl_next_word", "fl_parse_float"], code : r#"static float fl_read_float(int line, int col) {
+    char* word = fl_next_word();
+    if (word == NULL) {
+        fl_runtime_error(line, col, "read_float() reached the end of the input");
+    }
+    return fl_parse_float(word, line, col);
+}"# },
+    Entry { name : "fl_try_read_line", needs : &["fl_opt_string", "fl_next_line"], code : r#"static fl_opt_string fl_try_read_line(void) {
+    char* s = fl_next_line();
+    fl_opt_string out = { .tag = s == NULL };
+    out.as.Some._0 = s;
+    return out;
+}"# },
+    Entry { name : "fl_try_read_int", needs : &["fl_opt_int", "fl_next_word", "fl_parse_int"], code : r#"static fl_opt_int fl_try_read_int(int line, int col) {
+    char* word = fl_next_word();
+    fl_opt_int out = { .tag = word == NULL };
+    out.as.Some._0 = word == NULL ? 0 : fl_parse_int(word, line, col);
+    return out;
+}"# },
+    Entry { name : "fl_try_read_float", needs : &["fl_opt_float", "fl_next_word", "fl_parse_float"], code : r#"static fl_opt_float fl_try_read_float(int line, int col) {
+    char* word = fl_next_word();
+    fl_opt_float out = { .tag = word == NULL };
+    out.as.Some._0 = word == NULL ? 0 : fl_parse_float(word, line, col);
+    return out;
+}"# },
+
+    // a file that can't be opened, read or written is a runtime error saying why
+    Entry { name : "fl_read_file", needs : &["fl_alloc", "fl_runtime_error"], code : r#"static char* fl_read_file(const char* path, int line, int col) {
+    FILE* f = fopen(path, "rb");
+    if (f == NULL) {
+        fl_runtime_error(line, col, "Can't read '%s': %s", path, strerror(errno));
+    }
+    size_t len = 0;
+    size_t cap = 4096;
+    char* buf = fl_alloc(cap);
+    size_t n;
+    while ((n = fread(buf + len, 1, cap - len - 1, f)) > 0) {
+        len += n;
+        if (len + 1 == cap) {
+            char* grown = fl_alloc(cap * 2);
+            memcpy(grown, buf, len);
+            buf = grown;
+            cap *= 2;
+        }
+    }
+    if (ferror(f)) {
+        int error = errno;
+        fclose(f);
+        fl_runtime_error(line, col, "Can't read '%s': %s", path, strerror(error));
+    }
+    fclose(f);
+    buf[len] = '\0';
+    return buf;
+}"# },
+    // a line break at the very end doesn't start another line
+    Entry { name : "fl_read_lines", needs : &["fl_array", "fl_array_new", "fl_array_push", "fl_copy", "fl_read_file"], code : r#"static fl_array* fl_read_lines(const char* path, int line, int col) {
+    char* rest = fl_read_file(path, line, col);
+    fl_array* lines = fl_array_new(sizeof(char*), 0, NULL);
+    while (*rest != '\0') {
+        char* end = strchr(rest, '\n');
+        size_t len = end == NULL ? strlen(rest) : (size_t) (end - rest);
+        char* s = fl_copy(rest, len > 0 && rest[len - 1] == '\r' ? len - 1 : len);
+        fl_array_push(lines, &s);
+        rest = end == NULL ? rest + len : end + 1;
+    }
+    return lines;
+}"# },
+    Entry { name : "fl_put_file", needs : &["fl_runtime_error"], code : r#"static void fl_put_file(const char* path, const char* contents, const char* mode, int line, int col) {
+    FILE* f = fopen(path, mode);
+    if (f == NULL) {
+        fl_runtime_error(line, col, "Can't write '%s': %s", path, strerror(errno));
+    }
+    size_t len = strlen(contents);
+    bool ok = fwrite(contents, 1, len, f) == len;
+    int error = errno;
+    if (fclose(f) != 0 && ok) {
+        ok = false;
+        error = errno;
+    }
+    if (!ok) {
+        fl_runtime_error(line, col, "Can't write '%s': %s", path, strerror(error));
+    }
+}"# },
+    Entry { name : "fl_write_file", needs : &["fl_put_file"], code : r#"static void fl_write_file(const char* path, const char* contents, int line, int col) {
+    fl_put_file(path, contents, "wb", line, col);
+}"# },
+    Entry { name : "fl_append_file", needs : &["fl_put_file"], code : r#"static void fl_append_file(const char* path, const char* contents, int line, int col) {
+    fl_put_file(path, contents, "ab", line, col);
+}"# },
+    Entry { name : "fl_file_exists", needs : &[], code : r#"static bool fl_file_exists(const char* path) {
+    FILE* f = fopen(path, "rb");
+    if (f == NULL) {
+        return false;
+    }
+    fclose(f);
+    return true;
+}"# },
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Memory {
+    #[default]
+    Gc, // Boehm GC, needs gc.h and -lgc
+    Arena,
+    Malloc,
+}
+
+impl Memory {
+    pub fn from_name(name : &str) -> Option<Memory> {
+        match name {
+            "gc" => Some(Memory::Gc),
+            "arena" => Some(Memory::Arena),
+            "malloc" => Some(Memory::Malloc),
+            _ => None,
+        }
+    }
+
+    // libraries the program has to be linked with
+    pub fn libs(self) -> &'static [&'static str] {
+        match self {
+            Memory::Gc => &["gc"],
+            Memory::Arena | Memory::Malloc => &[],
+        }
+    }
+}
+
+// the runtime for `code`, the generated program. `source_name` is the FunLang
+// file runtime errors point to
+pub fn prelude(memory : Memory, source_name : &str, code : &str) -> String {
+    let mut out = HEADERS.to_string();
+    let allocator = match memory {
+        Memory::Gc => {
+            out.push_str("#include <gc.h>\n\n");
+            GC
+        }
+        Memory::Arena => ARENA,
+        Memory::Malloc => MALLOC,
+    };
+    out.push_str(&format!("#define FL_SOURCE {}\n\n", codegen::c_string_literal(source_name)));
+
+    // what the program names, then what those entries need. Needs come first
+    // in the table, so one pass from the end finds them all
+    let table : Vec<&Entry> = allocator.iter().chain(RUNTIME).collect();
+    let mut wanted = identifiers(code);
+    for entry in table.iter().rev() {
+        if wanted.contains(entry.name) {
+            wanted.extend(entry.needs);
+        }
+    }
+    for entry in table.iter().filter(|entry| wanted.contains(entry.name)) {
+        out.push_str(entry.code);
+        out.push_str("\n\n");
+    }
+    out
+}
+
+fn identifiers(code : &str) -> HashSet<&str> {
+    code.split(|c : char| !(c.is_ascii_alphanumeric() || c == '_')).filter(|word| !word.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_need_what_they_use() {
+        for allocator in [GC, ARENA, MALLOC] {
+            let table : Vec<&Entry> = allocator.iter().chain(RUNTIME).collect();
+            for (i, entry) in table.iter().enumerate() {
+                assert!(table[..i].iter().all(|other| other.name != entry.name), "{} is in the table twice", entry.name);
+                for need in entry.needs {
+                    assert!(table[..i].iter().any(|other| other.name == *need), "{} needs {}, which doesn't come before it", entry.name, need);
+                }
+                for used in identifiers(entry.code) {
+                    if used != entry.name && table.iter().any(|other| other.name == used) {
+                        assert!(entry.needs.contains(&used), "{} uses {} without needing it", entry.name, used);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn selects_what_the_program_uses() {
+        let prelude = prelude(Memory::Malloc, "test.fun", "int main() {\nfl_init();\nfl_array* a = fl_array_new(sizeof(int), 0, NULL);\n}\n");
+        for name in ["fl_init", "fl_alloc", "fl_out_of_memory", "fl_array_init", "fl_array_new"] {
+            assert!(prelude.contains(&format!("{}(", name)), "{} is missing", name);
+        }
+        for name in ["fl_runtime_error", "fl_array_push", "fl_concat", "fl_stdout"] {
+            assert!(!prelude.contains(name), "{} isn't used", name);
+        }
+    }
+}
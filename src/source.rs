@@ -0,0 +1,87 @@
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Hash, Eq, Serialize)]
+pub struct Span {
+    pub start : usize, // byte offset
+    pub end : usize,
+    pub line : usize, // 1-based
+    pub col : usize,
+    // which SourceFile the offsets are in. Only the REPL has more than one,
+    // an input per file
+    #[serde(skip)]
+    pub file : usize,
+}
+
+impl Span {
+    pub fn new(start : usize, end : usize, line : usize, col : usize) -> Self {
+        Self { start,end,line,col,file : 0 }
+    }
+
+    pub fn in_file(self, file : usize) -> Span {
+        Span { file, ..self }
+    }
+
+    // covers both spans, keeping the line/col of whichever comes first
+    pub fn to(self, other : Span) -> Span {
+        let first = if other.start < self.start { other } else { self };
+        Span {
+            start : first.start,
+            end : self.end.max(other.end),
+            line : first.line,
+            col : first.col,
+            file : first.file,
+        }
+    }
+}
+
+pub struct SourceFile {
+    pub name : String,
+    pub text : String,
+}
+
+impl SourceFile {
+    pub fn new(name : &str, text : String) -> Self {
+        Self { name : name.to_string(), text }
+    }
+
+    // the location line plus the offending source line with the span underlined:
+    //
+    //   --> test/1.fun:3:20
+    //    |
+    //  3 |     print("{}\n",hi;
+    //    |                    ^
+    pub fn snippet(&self, span : Span) -> String {
+        let start = self.char_boundary(span.start);
+        let line_start = self.text[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = self.text[start..].find('\n').map(|i| start + i).unwrap_or(self.text.len());
+        let line_text = &self.text[line_start..line_end];
+
+        let underline_end = self.char_boundary(span.end.clamp(start, line_end));
+        let caret_len = self.text[start..underline_end].chars().count().max(1);
+        let padding : String = self.text[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        let line_no = span.line.to_string();
+        let gutter = " ".repeat(line_no.len());
+
+        format!(
+            "{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+            gutter, self.name, span.line, span.col,
+            gutter,
+            line_no, line_text,
+            gutter, padding, "^".repeat(caret_len)
+        )
+    }
+
+    // `offset`, or the closest char boundary before it, so a span that doesn't
+    // belong to this text can't make snippet panic
+    fn char_boundary(&self, offset : usize) -> usize {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+}
@@ -0,0 +1,275 @@
+// Generic traversals over the AST. A pass overrides the hooks for the nodes it
+// cares about and calls the matching walk_/fold_ function to keep descending.
+
+use crate::nodes::*;
+
+pub trait Visitor {
+    fn visit_program(&mut self, program : &Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_function(&mut self, function : &Function) {
+        walk_function(self, function);
+    }
+
+    fn visit_stmt(&mut self, stmt : &Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr : &Expr) {
+        walk_expr(self, expr);
+    }
+}
+
+pub fn walk_program<V : Visitor + ?Sized>(v : &mut V, program : &Program) {
+    for function in &program.functions {
+        v.visit_function(function);
+    }
+    if let Some(main) = &program.main {
+        v.visit_function(main);
+    }
+}
+
+pub fn walk_function<V : Visitor + ?Sized>(v : &mut V, function : &Function) {
+    for stmt in &function.body {
+        v.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_stmt<V : Visitor + ?Sized>(v : &mut V, stmt : &Stmt) {
+    match &stmt.kind {
+        StmtKind::Print { args, .. } => {
+            for arg in args {
+                v.visit_expr(arg);
+            }
+        }
+        StmtKind::Let { value, .. } | StmtKind::Assign { value, .. } => v.visit_expr(value),
+        StmtKind::Set { target, value } => {
+            v.visit_expr(target);
+            v.visit_expr(value);
+        }
+        StmtKind::If { branches, else_body } => {
+            for (cond, body) in branches {
+                v.visit_expr(cond);
+                for stmt in body {
+                    v.visit_stmt(stmt);
+                }
+            }
+            for stmt in else_body.iter().flatten() {
+                v.visit_stmt(stmt);
+            }
+        }
+        StmtKind::While { cond, body } => {
+            v.visit_expr(cond);
+            for stmt in body {
+                v.visit_stmt(stmt);
+            }
+        }
+        StmtKind::For { start, end, step, body, .. } => {
+            v.visit_expr(start);
+            v.visit_expr(end);
+            if let Some(step) = step {
+                v.visit_expr(step);
+            }
+            for stmt in body {
+                v.visit_stmt(stmt);
+            }
+        }
+        StmtKind::Return { value } => {
+            if let Some(value) = value {
+                v.visit_expr(value);
+            }
+        }
+        StmtKind::Match { scrutinee, arms } => {
+            v.visit_expr(scrutinee);
+            for (_, body) in arms {
+                for stmt in body {
+                    v.visit_stmt(stmt);
+                }
+            }
+        }
+        StmtKind::Expr(expr) => v.visit_expr(expr),
+    }
+}
+
+pub fn walk_expr<V : Visitor + ?Sized>(v : &mut V, expr : &Expr) {
+    match &expr.kind {
+        ExprKind::Int(_) | ExprKind::Float(_) | ExprKind::Str(_) | ExprKind::Bool(_) | ExprKind::Var { .. } => {}
+        ExprKind::Array(elements) => {
+            for element in elements {
+                v.visit_expr(element);
+            }
+        }
+        ExprKind::Index { array, index } => {
+            v.visit_expr(array);
+            v.visit_expr(index);
+        }
+        ExprKind::StructLit { fields, .. } => {
+            for (_, value) in fields {
+                v.visit_expr(value);
+            }
+        }
+        ExprKind::Field { base, .. } => v.visit_expr(base),
+        ExprKind::Variant { args, .. } => {
+            for arg in args {
+                v.visit_expr(arg);
+            }
+        }
+        ExprKind::Match { scrutinee, arms, .. } => {
+            v.visit_expr(scrutinee);
+            for (_, value) in arms {
+                v.visit_expr(value);
+            }
+        }
+        ExprKind::Unary { operand, .. } => v.visit_expr(operand),
+        ExprKind::Binary { left, right, .. } => {
+            v.visit_expr(left);
+            v.visit_expr(right);
+        }
+        ExprKind::Call { args, .. } => {
+            for arg in args {
+                v.visit_expr(arg);
+            }
+        }
+    }
+}
+
+// like Visitor, but takes the tree by value and builds a new one, for passes
+// that rewrite nodes
+pub trait Fold {
+    fn fold_program(&mut self, program : Program) -> Program {
+        fold_program(self, program)
+    }
+
+    fn fold_function(&mut self, function : Function) -> Function {
+        fold_function(self, function)
+    }
+
+    fn fold_stmt(&mut self, stmt : Stmt) -> Stmt {
+        fold_stmt(self, stmt)
+    }
+
+    fn fold_expr(&mut self, expr : Expr) -> Expr {
+        fold_expr(self, expr)
+    }
+}
+
+fn fold_block<F : Fold + ?Sized>(f : &mut F, body : Vec<Stmt>) -> Vec<Stmt> {
+    body.into_iter().map(|stmt| f.fold_stmt(stmt)).collect()
+}
+
+pub fn fold_program<F : Fold + ?Sized>(f : &mut F, program : Program) -> Program {
+    Program {
+        structs : program.structs,
+        enums : program.enums,
+        functions : program.functions.into_iter().map(|function| f.fold_function(function)).collect(),
+        main : program.main.map(|main| f.fold_function(main)),
+    }
+}
+
+pub fn fold_function<F : Fold + ?Sized>(f : &mut F, function : Function) -> Function {
+    Function {
+        body : fold_block(f, function.body),
+        ..function
+    }
+}
+
+pub fn fold_stmt<F : Fold + ?Sized>(f : &mut F, stmt : Stmt) -> Stmt {
+    let kind = match stmt.kind {
+        StmtKind::Print { format, format_span, args, stream, newline } => StmtKind::Print {
+            format,
+            format_span,
+            args : args.into_iter().map(|arg| f.fold_expr(arg)).collect(),
+            stream,
+            newline,
+        },
+        StmtKind::Let { name, declared_type, value, c_name, escapes } => StmtKind::Let {
+            name,
+            declared_type,
+            value : f.fold_expr(value),
+            c_name,
+            escapes,
+        },
+        StmtKind::Assign { name, value, c_name } => StmtKind::Assign {
+            name,
+            value : f.fold_expr(value),
+            c_name,
+        },
+        StmtKind::Set { target, value } => StmtKind::Set {
+            target : f.fold_expr(target),
+            value : f.fold_expr(value),
+        },
+        StmtKind::If { branches, else_body } => StmtKind::If {
+            branches : branches.into_iter().map(|(cond, body)| (f.fold_expr(cond), fold_block(f, body))).collect(),
+            else_body : else_body.map(|body| fold_block(f, body)),
+        },
+        StmtKind::While { cond, body } => StmtKind::While {
+            cond : f.fold_expr(cond),
+            body : fold_block(f, body),
+        },
+        StmtKind::For { var, c_var, start, end, step, body } => StmtKind::For {
+            var,
+            c_var,
+            start : f.fold_expr(start),
+            end : f.fold_expr(end),
+            step : step.map(|step| f.fold_expr(step)),
+            body : fold_block(f, body),
+        },
+        StmtKind::Return { value } => StmtKind::Return {
+            value : value.map(|value| f.fold_expr(value)),
+        },
+        StmtKind::Match { scrutinee, arms } => StmtKind::Match {
+            scrutinee : f.fold_expr(scrutinee),
+            arms : arms.into_iter().map(|(pattern, body)| (pattern, fold_block(f, body))).collect(),
+        },
+        StmtKind::Expr(expr) => StmtKind::Expr(f.fold_expr(expr)),
+    };
+    Stmt { kind, ..stmt }
+}
+
+pub fn fold_expr<F : Fold + ?Sized>(f : &mut F, expr : Expr) -> Expr {
+    let kind = match expr.kind {
+        ExprKind::Array(elements) => ExprKind::Array(elements.into_iter().map(|element| f.fold_expr(element)).collect()),
+        ExprKind::Index { array, index } => ExprKind::Index {
+            array : Box::new(f.fold_expr(*array)),
+            index : Box::new(f.fold_expr(*index)),
+        },
+        ExprKind::StructLit { name, fields } => ExprKind::StructLit {
+            name,
+            fields : fields.into_iter().map(|(field, value)| (field, f.fold_expr(value))).collect(),
+        },
+        ExprKind::Field { base, field, index } => ExprKind::Field {
+            base : Box::new(f.fold_expr(*base)),
+            field,
+            index,
+        },
+        ExprKind::Variant { enum_name, variant, args, index } => ExprKind::Variant {
+            enum_name,
+            variant,
+            args : args.into_iter().map(|arg| f.fold_expr(arg)).collect(),
+            index,
+        },
+        ExprKind::Match { scrutinee, arms, id } => ExprKind::Match {
+            scrutinee : Box::new(f.fold_expr(*scrutinee)),
+            arms : arms.into_iter().map(|(pattern, value)| (pattern, f.fold_expr(value))).collect(),
+            id,
+        },
+        ExprKind::Unary { op, op_span, operand } => ExprKind::Unary {
+            op,
+            op_span,
+            operand : Box::new(f.fold_expr(*operand)),
+        },
+        ExprKind::Binary { op, op_span, left, right } => ExprKind::Binary {
+            op,
+            op_span,
+            left : Box::new(f.fold_expr(*left)),
+            right : Box::new(f.fold_expr(*right)),
+        },
+        ExprKind::Call { name, args } => ExprKind::Call {
+            name,
+            args : args.into_iter().map(|arg| f.fold_expr(arg)).collect(),
+        },
+        leaf => leaf,
+    };
+    Expr { kind, ..expr }
+}
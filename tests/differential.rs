@@ -0,0 +1,67 @@
+// Runs the programs in tests/programs through the interpreter and through the
+// C backend, and expects the same output and exit code from both. A program
+// reads its standard input from the .in file next to it, if there is one, and
+// its output has to match the .out (stdout) and .err (stderr, as messages)
+// files next to it, if there are any.
+use std::fs::{self,File};
+use std::path::{Path,PathBuf};
+use std::process::{Command,Output,Stdio};
+
+fn funlang(program : &Path, args : &[&str]) -> Output {
+    let input = program.with_extension("in");
+    let stdin = if input.exists() { Stdio::from(File::open(&input).unwrap()) } else { Stdio::null() };
+    // from the program's directory, so messages name the file the same way on every machine
+    Command::new(env!("CARGO_BIN_EXE_funlang"))
+        .current_dir(program.parent().unwrap())
+        .arg("run")
+        .args(args)
+        .arg(program.file_name().unwrap())
+        .stdin(stdin)
+        .output()
+        .expect("funlang runs")
+}
+
+// stderr without the source snippets the interpreter adds to runtime errors,
+// and without the indentation of ` --> `, which depends on the line number's width
+fn messages(stderr : &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(stderr)
+        .lines()
+        .filter(|line| !line.trim_start_matches(|c : char| c == ' ' || c.is_ascii_digit()).starts_with('|'))
+        .map(|line| line.trim_start().to_string())
+        .collect()
+}
+
+#[test]
+fn interpreter_and_c_agree() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut programs : Vec<PathBuf> = fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "fun"))
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty());
+
+    for program in &programs {
+        let interpreted = funlang(program, &["--interpret"]);
+        if let Ok(expected) = fs::read_to_string(program.with_extension("out")) {
+            assert_eq!(String::from_utf8_lossy(&interpreted.stdout), expected, "stdout of {}", program.display());
+        }
+        if let Ok(expected) = fs::read_to_string(program.with_extension("err")) {
+            assert_eq!(messages(&interpreted.stderr), messages(expected.as_bytes()), "stderr of {}", program.display());
+        }
+        // the arena needs nothing but a C compiler
+        let compiled = funlang(program, &["--memory=arena"]);
+        if String::from_utf8_lossy(&compiled.stderr).contains("error[E0403]") {
+            eprintln!("skipping the C backend: no C compiler");
+            continue;
+        }
+        // the optimizer is where undefined behaviour in the generated C shows
+        let optimized = funlang(program, &["--memory=arena", "-O2"]);
+        for (compiled, how) in [(&compiled, "the default level"), (&optimized, "-O2")] {
+            let name = format!("{} at {}", program.display(), how);
+            assert_eq!(String::from_utf8_lossy(&compiled.stdout), String::from_utf8_lossy(&interpreted.stdout), "stdout of {}", name);
+            assert_eq!(messages(&compiled.stderr), messages(&interpreted.stderr), "stderr of {}", name);
+            assert_eq!(compiled.status.code(), interpreted.status.code(), "exit code of {}", name);
+        }
+    }
+}